@@ -1,9 +1,11 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::mem;
+use std::num::{NonZeroU16, NonZeroU32};
 use std::path::{PathBuf, Path};
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
@@ -20,16 +22,20 @@ struct Footer {
     shader_size: u32,
     pokes_size: u32,
     config_size: u32,
+    demo_size: u32,
+    rom_size: u32,
 }
 
 impl Footer {
-    fn new(snapshot_size: u32, shader_size: u32, pokes_size: u32, config_size: u32) -> Self {
+    fn new(snapshot_size: u32, shader_size: u32, pokes_size: u32, config_size: u32, demo_size: u32, rom_size: u32) -> Self {
         Self {
             magic: *FOOTER_MAGIC,
             snapshot_size,
             shader_size,
             pokes_size,
             config_size,
+            demo_size,
+            rom_size,
         }
     }
     
@@ -43,42 +49,278 @@ impl Footer {
     }
 }
 
+/// `cheats_enabled` / `cheats_frozen`: the names of the trainers, or true/false for all of them
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum CheatSelection {
+    All(bool),
+    Trainers(Vec<String>),
+}
+
+/// Config format read by the runner (see config.schema.json)
+const CONFIG_VERSION: u64 = 2;
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum MachineModel {
+    #[serde(rename = "48K")]
+    Spectrum48K,
+    #[serde(rename = "128K")]
+    Spectrum128K,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum FilteringMode {
+    Nearest,
+    Linear,
+    Scanlines,
+    Embedded,
+    Custom,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum JoystickMode {
+    Off,
+    Kempston,
+    Sinclair1,
+    Sinclair2,
+    Cursor,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum AyStereoMode {
+    #[serde(rename = "ABC")]
+    Abc,
+    #[serde(rename = "ACB")]
+    Acb,
+    Mono,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum AyLogFormat {
+    Psg,
+    Ym,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum BorderMode {
+    Full,
+    Minimal,
+    None,
+}
+
+/// `persist_regions` entry: `length` bytes from `address`, in a 128K RAM `bank` if given
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "RegionFields")]
+struct PersistRegion {
+    address: u16,
+    length: u16,
+    bank: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct RegionFields {
+    address: u16,
+    length: u16,
+    bank: Option<u8>,
+}
+
+// Checked while the config is loaded, so bad regions get the usual config diagnostics
+impl TryFrom<RegionFields> for PersistRegion {
+    type Error = String;
+
+    fn try_from(fields: RegionFields) -> Result<Self, String> {
+        let RegionFields { address, length, bank } = fields;
+        let end = address as u32 + length as u32;
+        match bank {
+            Some(bank) if bank > 7 => return Err(format!("bank {} is not a 128K RAM bank (0-7)", bank)),
+            Some(bank) if (address as u32 & 0x3FFF) + length as u32 > 0x4000 => {
+                return Err(format!("region at {} ({} bytes) runs past the end of bank {}", address, length, bank));
+            }
+            _ if end > 0x10000 => return Err(format!("region at {} ({} bytes) runs past 65535", address, length)),
+            _ => {}
+        }
+        Ok(Self { address, length, bank })
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum Hotkey {
+    Info,
+    Filter,
+    Joystick,
+    Border,
+    Fullscreen,
+    ToggleCheats,
+    VolumeDown,
+    VolumeUp,
+    Mute,
+    FullSpeed,
+    StartFresh,
+    Screenshot,
+    CheatMenu,
+    CheatFinder,
+    Rewind,
+    // Was rzx_record before recordings got their own format
+    #[serde(alias = "rzx_record")]
+    InputRecord,
+    AudioStats,
+    AyMode,
+    AvRecord,
+    AyLog,
+    Debugger,
+    Quit,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum JoyDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+}
+
+/// `keymap` section: host key names are checked by the runner
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct KeymapConfig {
+    #[serde(default)]
+    hotkeys: BTreeMap<Hotkey, Option<String>>,
+    #[serde(default)]
+    joystick: BTreeMap<JoyDirection, Option<String>>,
+    #[serde(default)]
+    keys: BTreeMap<String, Option<String>>,
+}
+
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Config {
+    #[serde(default = "default_config_version")]
+    config_version: u32,
+    machine: Option<MachineModel>,
+    rom: Option<String>,
     #[serde(default = "default_fullscreen")]
     fullscreen: bool,
-    filtering: Option<String>,
+    filtering: Option<FilteringMode>,
     #[serde(default = "default_joystick")]
-    joystick: String,
+    joystick: JoystickMode,
     #[serde(default = "default_border")]
-    border: String,
+    border: BorderMode,
+    #[serde(default = "default_cheats")]
+    cheats_enabled: CheatSelection,
     #[serde(default = "default_cheats")]
-    cheats_enabled: bool,
+    cheats_frozen: CheatSelection,
     #[serde(default = "default_volume")]
     volume: u8,
+    #[serde(default = "default_resume")]
+    resume: bool,
+    #[serde(default = "default_rewind_seconds")]
+    rewind_seconds: u32,
+    #[serde(default = "default_rewind_interval")]
+    rewind_interval: u32,
+    #[serde(default = "default_rewind_memory")]
+    rewind_memory_mb: u32,
+    #[serde(default = "default_audio_latency")]
+    audio_latency_ms: u32,
+    audio_device: Option<String>,
+    audio_sample_rate: Option<NonZeroU32>,
+    audio_channels: Option<NonZeroU16>,
+    #[serde(default = "default_ay_mode")]
+    ay_mode: AyStereoMode,
+    #[serde(default = "default_stereo_width")]
+    stereo_width: u8,
+    screenshot_dir: Option<String>,
+    #[serde(default = "default_screenshot_scale")]
+    screenshot_scale: u8,
+    #[serde(default = "default_screenshot_filtered")]
+    screenshot_filtered: bool,
+    #[serde(default = "default_ay_log_format")]
+    ay_log_format: AyLogFormat,
+    #[serde(default = "default_persist_regions")]
+    persist_regions: Vec<PersistRegion>,
+    #[serde(default = "default_keymap")]
+    keymap: KeymapConfig,
 }
 
+#[allow(dead_code)]
+fn default_config_version() -> u32 { CONFIG_VERSION as u32 }
 #[allow(dead_code)]
 fn default_fullscreen() -> bool { true }
 #[allow(dead_code)]
-fn default_joystick() -> String { "Off".to_string() }
+fn default_joystick() -> JoystickMode { JoystickMode::Off }
 #[allow(dead_code)]
-fn default_border() -> String { "Full".to_string() }
+fn default_border() -> BorderMode { BorderMode::Full }
 #[allow(dead_code)]
-fn default_cheats() -> bool { false }
+fn default_cheats() -> CheatSelection { CheatSelection::All(false) }
 #[allow(dead_code)]
 fn default_volume() -> u8 { 100 }
+#[allow(dead_code)]
+fn default_resume() -> bool { false }
+#[allow(dead_code)]
+fn default_rewind_seconds() -> u32 { 20 }
+#[allow(dead_code)]
+fn default_rewind_interval() -> u32 { 5 }
+#[allow(dead_code)]
+fn default_rewind_memory() -> u32 { 32 }
+#[allow(dead_code)]
+fn default_audio_latency() -> u32 { 200 }
+#[allow(dead_code)]
+fn default_ay_mode() -> AyStereoMode { AyStereoMode::Abc }
+#[allow(dead_code)]
+fn default_stereo_width() -> u8 { 100 }
+#[allow(dead_code)]
+fn default_screenshot_scale() -> u8 { 1 }
+#[allow(dead_code)]
+fn default_screenshot_filtered() -> bool { false }
+#[allow(dead_code)]
+fn default_ay_log_format() -> AyLogFormat { AyLogFormat::Psg }
+#[allow(dead_code)]
+fn default_persist_regions() -> Vec<PersistRegion> { Vec::new() }
+#[allow(dead_code)]
+fn default_keymap() -> KeymapConfig { KeymapConfig::default() }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            config_version: CONFIG_VERSION as u32,
+            machine: None,
+            rom: None,
             fullscreen: true,
             filtering: None,
-            joystick: "Off".to_string(),
-            border: "Full".to_string(),
-            cheats_enabled: false,
+            joystick: JoystickMode::Off,
+            border: BorderMode::Full,
+            cheats_enabled: CheatSelection::All(false),
+            cheats_frozen: CheatSelection::All(false),
             volume: 100,
+            resume: false,
+            rewind_seconds: 20,
+            rewind_interval: 5,
+            rewind_memory_mb: 32,
+            audio_latency_ms: 200,
+            audio_device: None,
+            audio_sample_rate: None,
+            audio_channels: None,
+            ay_mode: AyStereoMode::Abc,
+            stereo_width: 100,
+            screenshot_dir: None,
+            screenshot_scale: 1,
+            screenshot_filtered: false,
+            ay_log_format: AyLogFormat::Psg,
+            persist_regions: Vec::new(),
+            keymap: KeymapConfig::default(),
         }
     }
 }
@@ -114,6 +356,14 @@ struct Args {
     /// Path to a JSON config file to embed (Optional) (default search: input_name.json, config.json)
     #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// Path to a zexe input recording (.zir) to embed as attract-mode demo (Optional) (default: input_name.zir)
+    #[arg(short, long)]
+    demo: Option<PathBuf>,
+
+    /// Path to a ROM image to use instead of the built-in one, 16K for 48K or 32K for 128K (Optional) (default: input_name.rom)
+    #[arg(long)]
+    rom: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -203,22 +453,62 @@ fn main() -> Result<()> {
         println!("Embedding config from {:?}...", path);
         let mut config_file = File::open(path).context("Failed to open config file")?;
         config_file.read_to_end(&mut config_data)?;
+        validate_config(&config_data)?;
+    }
+
+    // 6. Optional Demo (input recording)
+    let mut demo_data = Vec::new();
+    let demo_path = if let Some(path) = args.demo {
+        Some(path)
+    } else {
+        let mut auto_path = args.input.clone();
+        auto_path.set_extension("zir");
+        if auto_path.exists() { Some(auto_path) } else { None }
+    };
+
+    if let Some(path) = demo_path {
+        println!("Embedding demo from {:?}...", path);
+        let mut demo_file = File::open(path).context("Failed to open demo file")?;
+        demo_file.read_to_end(&mut demo_data)?;
+    }
+
+    // 7. Optional ROM image
+    let mut rom_data = Vec::new();
+    let rom_path = if let Some(path) = args.rom {
+        Some(path)
+    } else {
+        let mut auto_path = args.input.clone();
+        auto_path.set_extension("rom");
+        if auto_path.exists() { Some(auto_path) } else { None }
+    };
+
+    if let Some(path) = rom_path {
+        println!("Embedding ROM from {:?}...", path);
+        let mut rom_file = File::open(path).context("Failed to open ROM file")?;
+        rom_file.read_to_end(&mut rom_data)?;
+        if rom_data.len() != 0x4000 && rom_data.len() != 0x8000 {
+            return Err(anyhow!("ROM must be 16384 bytes (48K) or 32768 bytes (128K), not {}", rom_data.len()));
+        }
     }
 
-    // 6. Prepare and Compress data
+    // 8. Prepare and Compress data
     let compressed_snapshot = compress_data(&snapshot_data)?;
     let compressed_shader = if !shader_data.is_empty() { Some(compress_data(&shader_data)?) } else { None };
     let compressed_pokes = if !pokes_data.is_empty() { Some(compress_data(&pokes_data)?) } else { None };
     let compressed_config = if !config_data.is_empty() { Some(compress_data(&config_data)?) } else { None };
+    let compressed_demo = if !demo_data.is_empty() { Some(compress_data(&demo_data)?) } else { None };
+    let compressed_rom = if !rom_data.is_empty() { Some(compress_data(&rom_data)?) } else { None };
 
     let footer = Footer::new(
         compressed_snapshot.len() as u32, 
         compressed_shader.as_ref().map(|v| v.len()).unwrap_or(0) as u32, 
         compressed_pokes.as_ref().map(|v| v.len()).unwrap_or(0) as u32,
-        compressed_config.as_ref().map(|v| v.len()).unwrap_or(0) as u32
+        compressed_config.as_ref().map(|v| v.len()).unwrap_or(0) as u32,
+        compressed_demo.as_ref().map(|v| v.len()).unwrap_or(0) as u32,
+        compressed_rom.as_ref().map(|v| v.len()).unwrap_or(0) as u32
     );
 
-    // 7. Write Output
+    // 9. Write Output
     let mut output_file = File::create(&output_path).context("Failed to create output file")?;
     output_file.write_all(&runner_data)?;
     output_file.write_all(&compressed_snapshot)?;
@@ -231,6 +521,12 @@ fn main() -> Result<()> {
     if let Some(v) = compressed_config {
         output_file.write_all(&v)?;
     }
+    if let Some(v) = compressed_demo {
+        output_file.write_all(&v)?;
+    }
+    if let Some(v) = compressed_rom {
+        output_file.write_all(&v)?;
+    }
     output_file.write_all(footer.as_bytes())?;
 
     #[cfg(unix)]
@@ -245,3 +541,29 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+// Catches config mistakes at bundle time rather than when the game starts. Version 1
+// configs are only warned about, since the runner migrates their values.
+fn validate_config(data: &[u8]) -> Result<()> {
+    let mut value: serde_json::Value = serde_json::from_slice(data).context("Config file is not valid JSON")?;
+    let Some(fields) = value.as_object_mut() else {
+        return Err(anyhow!("Config file must be a JSON object"));
+    };
+    fields.remove("$schema");
+    let known = serde_json::to_value(Config::default())?;
+    for key in fields.keys().filter(|k| known.get(k.as_str()).is_none()) {
+        println!("Warning: unknown config setting {:?} will be ignored", key);
+    }
+    let version = fields.get("config_version").map(|v| v.as_u64().unwrap_or(0)).unwrap_or(1);
+    if version > CONFIG_VERSION {
+        println!("Warning: config version {} is newer than {}", version, CONFIG_VERSION);
+    }
+    match serde_json::from_value::<Config>(value) {
+        Ok(_) => Ok(()),
+        Err(e) if version < CONFIG_VERSION => {
+            println!("Warning: {} (the runner will migrate this version {} config)", e, version);
+            Ok(())
+        }
+        Err(e) => Err(anyhow!("Invalid config file: {}", e)),
+    }
+}
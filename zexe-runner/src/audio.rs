@@ -0,0 +1,299 @@
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Rate used by the silent sink when there is no output device
+const NULL_SAMPLE_RATE: u32 = 44100;
+const NULL_CHANNELS: u16 = 2;
+
+// The emulator's output is resampled by at most this fraction to hold the buffer
+// at the target latency, so the audio and emulation clocks never drift apart
+const MAX_RATE_ADJUST: f64 = 0.005;
+const RATE_SMOOTHING: f64 = 0.05;
+
+/// Output options from the config and command line; `None` uses the device default
+pub struct AudioSettings {
+    pub latency_ms: u32,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+pub struct AudioStats {
+    pub latency_ms: u32,
+    pub underruns: u64,
+    pub overruns: u64,
+    // Current resampling adjustment in percent
+    pub rate_adjust: f64,
+}
+
+enum Sink {
+    Device(cpal::Stream),
+    // Thread draining the buffer at the sample rate, standing in for a sound card
+    Null {
+        running: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    },
+}
+
+/// Audio output fed by the emulator. Falls back to a silent sink so the
+/// game still runs on machines without sound hardware.
+pub struct AudioOutput {
+    sink: Sink,
+    producer: HeapProducer<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+
+    // Rate control
+    target_fill: usize,
+    ratio: f64,
+    resample_pos: f64,
+    last_frame: (f32, f32),
+    underruns: Arc<AtomicU64>,
+    overruns: u64,
+    overrun_in_frame: bool,
+}
+
+impl AudioOutput {
+    pub fn open(settings: &AudioSettings) -> Self {
+        match Self::open_device(settings) {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("No audio output ({:?}), continuing without sound", e);
+                Self::open_null(settings)
+            }
+        }
+    }
+
+    fn new(sink: Sink, producer: HeapProducer<f32>, sample_rate: u32, channels: u16, latency_ms: u32, underruns: Arc<AtomicU64>) -> Self {
+        Self {
+            sink,
+            producer,
+            sample_rate,
+            channels,
+            target_fill: (sample_rate as u64 * channels as u64 * latency_ms as u64 / 1000) as usize,
+            ratio: 1.0,
+            resample_pos: 0.0,
+            last_frame: (0.0, 0.0),
+            underruns,
+            overruns: 0,
+            overrun_in_frame: false,
+        }
+    }
+
+    fn buffer_size(sample_rate: u32, channels: u16, latency_ms: u32) -> usize {
+        // At least 1.0s for RDP, and room above the target latency
+        let per_second = sample_rate as usize * channels as usize;
+        per_second.max(per_second * latency_ms as usize * 2 / 1000)
+    }
+
+    fn open_device(settings: &AudioSettings) -> Result<Self> {
+        let latency_ms = settings.latency_ms;
+        let audio_host = cpal::default_host();
+        let audio_device = select_device(&audio_host, settings.device.as_deref())?;
+        let config = select_config(&audio_device, settings)?;
+        let sample_rate = config.sample_rate().0;
+        let channels = config.channels();
+
+        let rb = HeapRb::<f32>::new(Self::buffer_size(sample_rate, channels, latency_ms));
+        let (producer, mut consumer) = rb.split();
+
+        let underruns = Arc::new(AtomicU64::new(0));
+        let callback_underruns = underruns.clone();
+        let mut last_sample = 0.0;
+        let mut stream_config: cpal::StreamConfig = config.into();
+        // Set hardware buffer size based on requested latency
+        let buffer_frames = (sample_rate as f32 * (latency_ms as f32 / 1000.0)) as u32;
+        stream_config.buffer_size = cpal::BufferSize::Fixed(buffer_frames.max(512));
+
+        let stream = audio_device.build_output_stream(
+            &stream_config,
+            move |data: &mut [f32], _| {
+                let mut starved = false;
+                for sample in data.iter_mut() {
+                    if let Some(s) = consumer.pop() {
+                        last_sample = s;
+                        *sample = s;
+                    } else {
+                        *sample = last_sample;
+                        starved = true;
+                    }
+                }
+                if starved {
+                    callback_underruns.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+            |err| eprintln!("Audio stream error: {:?}", err),
+            None
+        )?;
+
+        Ok(Self::new(Sink::Device(stream), producer, sample_rate, channels, latency_ms, underruns))
+    }
+
+    fn open_null(settings: &AudioSettings) -> Self {
+        let sample_rate = settings.sample_rate.unwrap_or(NULL_SAMPLE_RATE);
+        let channels = settings.channels.unwrap_or(NULL_CHANNELS);
+        let rb = HeapRb::<f32>::new(Self::buffer_size(sample_rate, channels, settings.latency_ms));
+        let (producer, consumer) = rb.split();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = std::thread::spawn(move || drain_silently(consumer, thread_running, sample_rate, channels));
+
+        Self::new(
+            Sink::Null { running, thread: Some(thread) },
+            producer,
+            sample_rate,
+            channels,
+            settings.latency_ms,
+            Arc::new(AtomicU64::new(0)),
+        )
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.sink, Sink::Null { .. })
+    }
+
+    pub fn play(&self) -> Result<()> {
+        if let Sink::Device(stream) = &self.sink {
+            stream.play()?;
+        }
+        Ok(())
+    }
+
+    /// Queues one stereo sample from the emulator, resampled by the current ratio
+    pub fn push_frame(&mut self, left: f32, right: f32) {
+        let step = 1.0 / self.ratio;
+        let (prev_left, prev_right) = self.last_frame;
+        while self.resample_pos < 1.0 {
+            let t = self.resample_pos as f32;
+            self.write(prev_left + (left - prev_left) * t, prev_right + (right - prev_right) * t);
+            self.resample_pos += step;
+        }
+        self.resample_pos -= 1.0;
+        self.last_frame = (left, right);
+    }
+
+    fn write(&mut self, left: f32, right: f32) {
+        let ok = if self.channels == 2 {
+            self.producer.push(left).is_ok() && self.producer.push(right).is_ok()
+        } else {
+            let val = (left + right) / 2.0;
+            (0..self.channels).all(|_| self.producer.push(val).is_ok())
+        };
+        if !ok {
+            self.overrun_in_frame = true;
+        }
+    }
+
+    /// Called after each emulated frame: steers the resampling ratio towards the target fill level
+    pub fn end_frame(&mut self) {
+        if self.overrun_in_frame {
+            self.overruns += 1;
+            self.overrun_in_frame = false;
+        }
+        if self.target_fill == 0 {
+            return;
+        }
+        let error = (self.producer.len() as f64 - self.target_fill as f64) / self.target_fill as f64;
+        let desired = 1.0 - error.clamp(-1.0, 1.0) * MAX_RATE_ADJUST;
+        self.ratio += (desired - self.ratio) * RATE_SMOOTHING;
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        let per_ms = self.sample_rate as f64 * self.channels as f64 / 1000.0;
+        AudioStats {
+            latency_ms: (self.producer.len() as f64 / per_ms) as u32,
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns,
+            rate_adjust: (self.ratio - 1.0) * 100.0,
+        }
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        if let Sink::Null { running, thread } = &mut self.sink {
+            running.store(false, Ordering::SeqCst);
+            if let Some(thread) = thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Prints the output devices and their supported formats (for --list-audio-devices)
+pub fn list_devices() -> Result<()> {
+    let audio_host = cpal::default_host();
+    let default_name = audio_host.default_output_device().and_then(|d| d.name().ok());
+    for device in audio_host.output_devices()? {
+        let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+        let marker = if Some(&name) == default_name.as_ref() { " (default)" } else { "" };
+        println!("{}{}", name, marker);
+        if let Ok(configs) = device.supported_output_configs() {
+            for range in configs {
+                println!(
+                    "    {} ch, {}-{} Hz, {:?}",
+                    range.channels(),
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0,
+                    range.sample_format()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds a device by exact name, then by case-insensitive substring
+fn select_device(audio_host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device> {
+    if let Some(name) = name {
+        let devices: Vec<cpal::Device> = audio_host.output_devices()?.collect();
+        let lower = name.to_lowercase();
+        let found = devices.iter().position(|d| d.name().is_ok_and(|n| n == name))
+            .or_else(|| devices.iter().position(|d| d.name().is_ok_and(|n| n.to_lowercase().contains(&lower))));
+        match found {
+            Some(index) => return Ok(devices.into_iter().nth(index).unwrap()),
+            None => eprintln!("Audio device '{}' not found, using the default device", name),
+        }
+    }
+    audio_host.default_output_device().context("No audio device")
+}
+
+fn select_config(device: &cpal::Device, settings: &AudioSettings) -> Result<cpal::SupportedStreamConfig> {
+    let default = device.default_output_config()?;
+    if settings.sample_rate.is_none() && settings.channels.is_none() {
+        return Ok(default);
+    }
+    let sample_rate = settings.sample_rate.unwrap_or(default.sample_rate().0);
+    let channels = settings.channels.unwrap_or(default.channels());
+    let found = device.supported_output_configs()?
+        .filter(|r| r.channels() == channels && r.sample_format() == cpal::SampleFormat::F32)
+        .find(|r| (r.min_sample_rate().0..=r.max_sample_rate().0).contains(&sample_rate));
+    match found {
+        Some(range) => Ok(range.with_sample_rate(cpal::SampleRate(sample_rate))),
+        None => {
+            eprintln!("Audio format {} Hz / {} ch not supported, using the device default", sample_rate, channels);
+            Ok(default)
+        }
+    }
+}
+
+// Consumes samples at the rate a real device would, so the emulator sees the same back-pressure
+fn drain_silently(mut consumer: HeapConsumer<f32>, running: Arc<AtomicBool>, sample_rate: u32, channels: u16) {
+    let start = Instant::now();
+    let mut consumed: u64 = 0;
+    let per_second = sample_rate as u64 * channels as u64;
+
+    while running.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(10));
+        let due = start.elapsed().as_micros() as u64 * per_second / 1_000_000;
+        while consumed < due {
+            let _ = consumer.pop();
+            consumed += 1;
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+// AY register logs in the formats used by chiptune players
+//
+// PSG: "PSG\x1A" header, then register/value pairs with 0xFF marking each frame.
+// YM5: uncompressed, interleaved dump of the 16 registers per 50 Hz frame.
+
+// Registers 14 and 15 are the I/O port (keypad, RS232 on the 128K), not sound
+const SOUND_REGISTERS: usize = 14;
+// Clock of the AY in the 128K Spectrum
+const AY_CLOCK: u32 = 1_773_400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AyLogFormat {
+    Psg,
+    Ym,
+}
+
+impl AyLogFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Psg => "psg",
+            Self::Ym => "ym",
+        }
+    }
+}
+
+pub struct AyLog {
+    format: AyLogFormat,
+    registers: [u8; 16],
+    // PSG event stream
+    events: Vec<u8>,
+    // YM register dump per frame
+    frames: Vec<[u8; 16]>,
+    frame_count: usize,
+}
+
+impl AyLog {
+    /// Starts a log from the current register contents
+    pub fn new(format: AyLogFormat, registers: [u8; 16]) -> Self {
+        let mut events = Vec::new();
+        for (register, &value) in registers.iter().enumerate().take(SOUND_REGISTERS) {
+            events.push(register as u8);
+            events.push(value);
+        }
+        Self { format, registers, events, frames: Vec::new(), frame_count: 0 }
+    }
+
+    pub fn format(&self) -> AyLogFormat {
+        self.format
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Adds one emulated frame with the register writes made during it
+    pub fn push_frame(&mut self, writes: &[(u8, u8)]) {
+        self.frame_count += 1;
+        let mut envelope_written = false;
+        for &(register, value) in writes {
+            if register as usize >= SOUND_REGISTERS {
+                continue;
+            }
+            self.registers[register as usize] = value;
+            envelope_written |= register == 13;
+            if self.format == AyLogFormat::Psg {
+                self.events.push(register);
+                self.events.push(value);
+            }
+        }
+        match self.format {
+            AyLogFormat::Psg => self.events.push(0xFF),
+            AyLogFormat::Ym => {
+                let mut frame = self.registers;
+                // 0xFF in register 13 means "do not retrigger the envelope"
+                if !envelope_written && !self.frames.is_empty() {
+                    frame[13] = 0xFF;
+                }
+                frame[14] = 0;
+                frame[15] = 0;
+                self.frames.push(frame);
+            }
+        }
+    }
+
+    pub fn encode(&self, title: &str) -> Vec<u8> {
+        match self.format {
+            AyLogFormat::Psg => {
+                let mut out = Vec::with_capacity(16 + self.events.len());
+                out.extend_from_slice(b"PSG\x1A");
+                out.extend_from_slice(&[0; 12]);
+                out.extend_from_slice(&self.events);
+                out
+            }
+            AyLogFormat::Ym => {
+                let mut out = Vec::with_capacity(64 + self.frames.len() * 16);
+                out.extend_from_slice(b"YM5!LeOnArD!");
+                out.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+                out.extend_from_slice(&1u32.to_be_bytes()); // Interleaved
+                out.extend_from_slice(&0u16.to_be_bytes()); // No digidrums
+                out.extend_from_slice(&AY_CLOCK.to_be_bytes());
+                out.extend_from_slice(&50u16.to_be_bytes());
+                out.extend_from_slice(&0u32.to_be_bytes()); // Loop frame
+                out.extend_from_slice(&0u16.to_be_bytes()); // No additional data
+                for text in [title, "", "Logged with zexe"] {
+                    out.extend_from_slice(text.as_bytes());
+                    out.push(0);
+                }
+                for register in 0..16 {
+                    out.extend(self.frames.iter().map(|frame| frame[register]));
+                }
+                out.extend_from_slice(b"End!");
+                out
+            }
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::{Result, anyhow};
+
+use crate::cli;
+use crate::sna::SnaImage;
+
+// Breakpoints beyond plain PC addresses: conditions, memory watchpoints and port
+// breakpoints. The core has no memory or port hooks, so the debug hook decodes the
+// instruction about to run, and writes are found by comparing memory between frames.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    pub fn reads(self) -> bool {
+        self != Self::Write
+    }
+
+    pub fn writes(self) -> bool {
+        self != Self::Read
+    }
+}
+
+/// Inclusive address range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PortBreakpoint {
+    // 0x00-0xFF matches the low byte only (e.g. 0xFE), anything else the full port
+    pub port: u16,
+    pub access: Access,
+}
+
+impl PortBreakpoint {
+    pub fn matches(&self, port: u16, write: bool) -> bool {
+        let access_ok = if write { self.access.writes() } else { self.access.reads() };
+        let port_ok = if self.port <= 0xFF { port & 0xFF == self.port } else { port == self.port };
+        access_ok && port_ok
+    }
+
+    /// Whether an access whose low byte is known could match
+    pub fn may_match_low(&self, low: u8, write: bool) -> bool {
+        let access_ok = if write { self.access.writes() } else { self.access.reads() };
+        access_ok && self.port & 0xFF == low as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(&'static str),
+    // Byte at an address
+    Memory(u16),
+}
+
+/// Condition of a PC breakpoint, e.g. "A=5", "HL>=4000" or "(5C3A)!=0"
+#[derive(Debug, Clone, Copy)]
+pub struct Condition {
+    operand: Operand,
+    compare: Compare,
+    value: u16,
+}
+
+const REGISTERS: [&str; 19] = [
+    "AF", "BC", "DE", "HL", "IX", "IY", "SP", "PC", "A", "F", "B", "C", "D", "E", "H", "L", "I", "R", "IM",
+];
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim().to_uppercase();
+        let ops = [
+            ("==", Compare::Equal),
+            ("!=", Compare::NotEqual),
+            ("<=", Compare::LessEqual),
+            (">=", Compare::GreaterEqual),
+            ("=", Compare::Equal),
+            ("<", Compare::Less),
+            (">", Compare::Greater),
+        ];
+        let (pos, op_len, compare) = ops
+            .iter()
+            .find_map(|(op, cmp)| text.find(op).map(|pos| (pos, op.len(), *cmp)))
+            .ok_or_else(|| anyhow!("Invalid condition: {}", text))?;
+        let (lhs, rhs) = (text[..pos].trim(), text[pos + op_len..].trim());
+
+        let operand = if let Some(addr) = lhs.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+            Operand::Memory(cli::parse_u16(&format!("0x{}", addr.trim_start_matches("0X").trim_start_matches('$')))?)
+        } else {
+            let name = REGISTERS.iter().find(|r| **r == lhs).ok_or_else(|| anyhow!("Unknown register: {}", lhs))?;
+            Operand::Register(name)
+        };
+        // Values are hexadecimal, like everything else in the debugger
+        let value = cli::parse_u16(&format!("0x{}", rhs.trim_start_matches("0X").trim_start_matches('$')))?;
+        Ok(Self { operand, compare, value })
+    }
+
+    pub fn holds(&self, image: &SnaImage) -> bool {
+        let h = &image.header;
+        let actual = match self.operand {
+            Operand::Memory(addr) => image.read(addr) as u16,
+            Operand::Register(name) => match name {
+                "AF" => image.reg16(21),
+                "BC" => image.reg16(13),
+                "DE" => image.reg16(11),
+                "HL" => image.reg16(9),
+                "IX" => image.reg16(17),
+                "IY" => image.reg16(15),
+                "SP" => image.reg16(23),
+                "PC" => image.pc,
+                "A" => h[22] as u16,
+                "F" => h[21] as u16,
+                "B" => h[14] as u16,
+                "C" => h[13] as u16,
+                "D" => h[12] as u16,
+                "E" => h[11] as u16,
+                "H" => h[10] as u16,
+                "L" => h[9] as u16,
+                "I" => h[0] as u16,
+                "R" => h[20] as u16,
+                _ => h[25] as u16,
+            },
+        };
+        match self.compare {
+            Compare::Equal => actual == self.value,
+            Compare::NotEqual => actual != self.value,
+            Compare::Less => actual < self.value,
+            Compare::LessEqual => actual <= self.value,
+            Compare::Greater => actual > self.value,
+            Compare::GreaterEqual => actual >= self.value,
+        }
+    }
+}
+
+fn parse_access(text: Option<&str>) -> Result<Access> {
+    match text.map(|s| s.to_lowercase()).as_deref() {
+        None | Some("rw") => Ok(Access::ReadWrite),
+        Some("r") | Some("in") => Ok(Access::Read),
+        Some("w") | Some("out") => Ok(Access::Write),
+        Some(other) => Err(anyhow!("Invalid access: {}", other)),
+    }
+}
+
+#[derive(Default)]
+pub struct BreakpointSet {
+    // Conditions of PC breakpoints (all must hold)
+    pub conditions: Vec<(u16, Vec<Condition>)>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub ports: Vec<PortBreakpoint>,
+}
+
+impl BreakpointSet {
+    /// Reads --break ADDR[:COND,...], --watch START[-END][:r|w|rw] and --break-port PORT[:in|out].
+    /// Returns the set and the plain PC breakpoint addresses.
+    pub fn from_args(args: &[String]) -> Result<(Self, Vec<u16>)> {
+        let mut set = Self::default();
+        let mut addresses = Vec::new();
+        for spec in cli::values(args, "--break") {
+            let (addr, conditions) = spec.split_once(':').map(|(a, c)| (a, Some(c))).unwrap_or((spec, None));
+            let addr = cli::parse_u16(addr)?;
+            addresses.push(addr);
+            if let Some(conditions) = conditions {
+                let parsed = conditions.split(',').map(Condition::parse).collect::<Result<Vec<_>>>()?;
+                set.conditions.push((addr, parsed));
+            }
+        }
+        for spec in cli::values(args, "--watch") {
+            let mut parts = spec.split(':');
+            let range = parts.next().unwrap_or_default();
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (cli::parse_u16(start)?, cli::parse_u16(end)?),
+                None => {
+                    let addr = cli::parse_u16(range)?;
+                    (addr, addr)
+                }
+            };
+            set.watchpoints.push(Watchpoint { start: start.min(end), end: start.max(end), access: parse_access(parts.next())? });
+        }
+        for spec in cli::values(args, "--break-port") {
+            let mut parts = spec.split(':');
+            let port = cli::parse_u16(parts.next().unwrap_or_default())?;
+            set.ports.push(PortBreakpoint { port, access: parse_access(parts.next())? });
+        }
+        Ok((set, addresses))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty() && self.watchpoints.is_empty() && self.ports.is_empty()
+    }
+
+    pub fn has_write_watches(&self) -> bool {
+        self.watchpoints.iter().any(|w| w.access.writes())
+    }
+
+    /// Whether a PC breakpoint's conditions hold (no conditions always hold)
+    pub fn condition_holds(&self, addr: u16, image: &SnaImage) -> bool {
+        self.conditions
+            .iter()
+            .filter(|(a, _)| *a == addr)
+            .all(|(_, conditions)| conditions.iter().all(|c| c.holds(image)))
+    }
+
+    /// First watched address whose contents differ between two states
+    pub fn changed_watch(&self, before: &SnaImage, after: &SnaImage) -> Option<u16> {
+        self.watchpoints
+            .iter()
+            .filter(|w| w.access.writes())
+            .flat_map(|w| w.start..=w.end)
+            .find(|&addr| before.read(addr) != after.read(addr))
+    }
+}
+
+/// Register an instruction reads memory through
+#[derive(Debug, Clone, Copy)]
+pub enum Pointer {
+    Bc,
+    De,
+    Hl,
+    Ix,
+    Iy,
+    Sp,
+}
+
+/// Memory read made by an instruction, if any
+#[derive(Debug, Clone, Copy)]
+pub enum MemoryRead {
+    // LD A,(nn), LD HL,(nn), ...: the address is part of the instruction
+    Absolute { addr: u16, size: u16 },
+    // (HL), (IX+d), POP, block instructions, ...: the address is in a register
+    Indirect { pointer: Pointer, offset: i8, size: u16 },
+    // RET cc only reads the stack when its condition (bits 3-5 of the opcode) holds
+    Return { opcode: u8 },
+}
+
+impl MemoryRead {
+    /// First address and size of the read, given the registers before the instruction
+    pub fn range(self, image: &SnaImage) -> Option<(u16, u16)> {
+        match self {
+            Self::Absolute { addr, size } => Some((addr, size)),
+            Self::Indirect { pointer, offset, size } => {
+                let base = match pointer {
+                    Pointer::Bc => image.reg16(13),
+                    Pointer::De => image.reg16(11),
+                    Pointer::Hl => image.reg16(9),
+                    Pointer::Ix => image.reg16(17),
+                    Pointer::Iy => image.reg16(15),
+                    Pointer::Sp => image.reg16(23),
+                };
+                Some((base.wrapping_add_signed(offset as i16), size))
+            }
+            Self::Return { opcode } => {
+                // NZ, Z, NC, C, PO, PE, P, M
+                let flag = [0x40, 0x40, 0x01, 0x01, 0x04, 0x04, 0x80, 0x80][(opcode >> 3 & 7) as usize];
+                let set = image.header[21] & flag != 0;
+                (set == (opcode & 0x08 != 0)).then(|| (image.reg16(23), 2))
+            }
+        }
+    }
+}
+
+pub fn memory_read(bytes: [u8; 4]) -> Option<MemoryRead> {
+    let word = |lo: u8, hi: u8| u16::from_le_bytes([lo, hi]);
+    let via = |pointer, size| Some(MemoryRead::Indirect { pointer, offset: 0, size });
+    let indexed = |prefix, d: u8| {
+        let pointer = if prefix == 0xDD { Pointer::Ix } else { Pointer::Iy };
+        Some(MemoryRead::Indirect { pointer, offset: d as i8, size: 1 })
+    };
+    // LD r,(HL) (but not HALT), ALU A,(HL), INC (HL), DEC (HL); the same with (IX+d)/(IY+d)
+    let reads_hl = |op: u8| (op & 0xC7 == 0x46 && op != 0x76) || op & 0xC7 == 0x86 || op == 0x34 || op == 0x35;
+    match bytes {
+        [0x3A, lo, hi, _] => Some(MemoryRead::Absolute { addr: word(lo, hi), size: 1 }),
+        [0x2A, lo, hi, _] => Some(MemoryRead::Absolute { addr: word(lo, hi), size: 2 }),
+        [0xED, 0x4B | 0x5B | 0x6B | 0x7B, lo, hi] => Some(MemoryRead::Absolute { addr: word(lo, hi), size: 2 }),
+        [0xDD | 0xFD, 0x2A, lo, hi] => Some(MemoryRead::Absolute { addr: word(lo, hi), size: 2 }),
+        [0x0A, ..] => via(Pointer::Bc, 1),
+        [0x1A, ..] => via(Pointer::De, 1),
+        [op, ..] if reads_hl(op) => via(Pointer::Hl, 1),
+        [0xCB, op, ..] if op & 0x07 == 0x06 => via(Pointer::Hl, 1),
+        // Block transfers, compares and outputs, RRD, RLD
+        [0xED, 0xA0 | 0xA1 | 0xA3 | 0xA8 | 0xA9 | 0xAB | 0xB0 | 0xB1 | 0xB3 | 0xB8 | 0xB9 | 0xBB | 0x67 | 0x6F, ..] => {
+            via(Pointer::Hl, 1)
+        }
+        [prefix @ (0xDD | 0xFD), op, d, _] if reads_hl(op) => indexed(prefix, d),
+        [prefix @ (0xDD | 0xFD), 0xCB, d, _] => indexed(prefix, d),
+        // POP, RET, EX (SP),HL, RETN/RETI
+        [op, ..] if op & 0xCF == 0xC1 || op == 0xC9 || op == 0xE3 => via(Pointer::Sp, 2),
+        [op, ..] if op & 0xC7 == 0xC0 => Some(MemoryRead::Return { opcode: op }),
+        [0xDD | 0xFD, 0xE1 | 0xE3, ..] => via(Pointer::Sp, 2),
+        [0xED, op, ..] if op & 0xC7 == 0x45 => via(Pointer::Sp, 2),
+        _ => None,
+    }
+}
+
+/// Port access made by an instruction, if any
+#[derive(Debug, Clone, Copy)]
+pub enum PortAccess {
+    // IN A,(n) / OUT (n),A: the high byte comes from A
+    Immediate { low: u8, write: bool },
+    // IN r,(C) / OUT (C),r and the block instructions: the port is BC.
+    // Block outputs decrement B before the access.
+    ViaBc { write: bool, pre_decrement: bool },
+}
+
+impl PortAccess {
+    /// Port and whether it is written, given the registers before the instruction
+    pub fn port(self, image: &SnaImage) -> (u16, bool) {
+        let h = &image.header;
+        match self {
+            Self::Immediate { low, write } => ((h[22] as u16) << 8 | low as u16, write),
+            Self::ViaBc { write, pre_decrement } => {
+                let b = if pre_decrement { h[14].wrapping_sub(1) } else { h[14] };
+                ((b as u16) << 8 | h[13] as u16, write)
+            }
+        }
+    }
+}
+
+/// Byte written by an OUT instruction, given the registers before it
+pub fn out_value(bytes: [u8; 4], image: &SnaImage) -> u8 {
+    let h = &image.header;
+    match bytes {
+        [0xD3, ..] => h[22],
+        // OUT (C),r: B, C, D, E, H, L, 0, A
+        [0xED, op, ..] if op & 0xC7 == 0x41 => [h[14], h[13], h[12], h[11], h[10], h[9], 0, h[22]][(op >> 3 & 7) as usize],
+        // OUTI, OUTD, OTIR, OTDR
+        _ => image.read(image.reg16(9)),
+    }
+}
+
+pub fn port_access(bytes: [u8; 4]) -> Option<PortAccess> {
+    match bytes {
+        [0xD3, low, _, _] => Some(PortAccess::Immediate { low, write: true }),
+        [0xDB, low, _, _] => Some(PortAccess::Immediate { low, write: false }),
+        [0xED, op, _, _] if op & 0xC7 == 0x40 => Some(PortAccess::ViaBc { write: false, pre_decrement: false }),
+        [0xED, op, _, _] if op & 0xC7 == 0x41 => Some(PortAccess::ViaBc { write: true, pre_decrement: false }),
+        [0xED, 0xA2 | 0xAA | 0xB2 | 0xBA, _, _] => Some(PortAccess::ViaBc { write: false, pre_decrement: false }),
+        [0xED, 0xA3 | 0xAB | 0xB3 | 0xBB, _, _] => Some(PortAccess::ViaBc { write: true, pre_decrement: true }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> SnaImage {
+        let mut image = SnaImage::parse(&[0; 49179]).unwrap();
+        image.header[22] = 0x12; // A
+        image.header[14] = 0x01; // B
+        image.header[13] = 0xFD; // C
+        image.set_reg16(9, 0x4000); // HL
+        image.set_reg16(17, 0x6000); // IX
+        image.write(0x5C3A, 0xFF);
+        image
+    }
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn conditions() {
+        let image = image();
+        let holds = |text| Condition::parse(text).unwrap().holds(&image);
+        assert!(holds("a=12"));
+        assert!(holds("A == $12"));
+        assert!(!holds("A!=12"));
+        assert!(holds("HL>=4000"));
+        assert!(!holds("HL<4000"));
+        assert!(holds("(5C3A)>FE"));
+        assert!(holds("(0x5C3B)=0"));
+        assert!(Condition::parse("Q=1").is_err());
+        assert!(Condition::parse("HL").is_err());
+    }
+
+    #[test]
+    fn from_args() {
+        let (set, addresses) = BreakpointSet::from_args(&args(
+            "--break 0x8000:A=5,HL>4000 --break 32769 --watch 0x5C00-0x5B00:w --watch $4000 --break-port 0xFE:in",
+        ))
+        .unwrap();
+        assert_eq!(addresses, vec![0x8000, 0x8001]);
+        assert_eq!(set.conditions.len(), 1);
+        assert_eq!(set.conditions[0].1.len(), 2);
+        assert_eq!(set.watchpoints, vec![
+            Watchpoint { start: 0x5B00, end: 0x5C00, access: Access::Write },
+            Watchpoint { start: 0x4000, end: 0x4000, access: Access::ReadWrite },
+        ]);
+        assert!(set.ports[0].matches(0x12FE, false));
+        assert!(!set.ports[0].matches(0x12FE, true));
+        assert!(BreakpointSet::from_args(&args("--watch 0x4000:x")).is_err());
+        assert!(BreakpointSet::from_args(&args("--break-port 0x1FFFF")).is_err());
+    }
+
+    #[test]
+    fn memory_reads() {
+        let image = image();
+        let range = |bytes| memory_read(bytes).and_then(|read| read.range(&image));
+        assert_eq!(range([0x3A, 0x00, 0x50, 0]), Some((0x5000, 1)));
+        assert_eq!(range([0xED, 0x4B, 0x00, 0x50]), Some((0x5000, 2)));
+        assert_eq!(range([0x7E, 0, 0, 0]), Some((0x4000, 1)));
+        assert_eq!(range([0xDD, 0x7E, 0xFE, 0]), Some((0x5FFE, 1)));
+        // HALT sits among the LD r,(HL) opcodes but reads nothing
+        assert_eq!(range([0x76, 0, 0, 0]), None);
+        assert_eq!(range([0x77, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn port_accesses() {
+        let image = image();
+        let port = |bytes| port_access(bytes).map(|access| access.port(&image));
+        assert_eq!(port([0xD3, 0xFE, 0, 0]), Some((0x12FE, true)));
+        assert_eq!(port([0xDB, 0x1F, 0, 0]), Some((0x121F, false)));
+        assert_eq!(port([0xED, 0x78, 0, 0]), Some((0x01FD, false)));
+        // OUTI decrements B first
+        assert_eq!(port([0xED, 0xA3, 0, 0]), Some((0x00FD, true)));
+        assert_eq!(port([0x00, 0, 0, 0]), None);
+        assert_eq!(out_value([0xD3, 0xFE, 0, 0], &image), 0x12);
+        assert_eq!(out_value([0xED, 0x41, 0, 0], &image), 0x01);
+        assert_eq!(out_value([0xED, 0x71, 0, 0], &image), 0);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use crate::cheat_menu::{COLOR_SELECTED, COLOR_TEXT, COLOR_TITLE};
+use crate::sna::SnaImage;
+
+// Memory search for making new POKEs. A search starts from a snapshot of RAM
+// (0x4000-0xFFFF as currently paged) and is narrowed down, between stretches of play,
+// to the bytes that equal a value or that went down or up since the last step.
+
+const RAM_START: u16 = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal(u8),
+    Decreased,
+    Increased,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    // Value to search for
+    Search,
+    // Value to write to the selected address
+    Poke,
+}
+
+/// A POKE made from the finder, kept for the .pok export
+#[derive(Debug, Clone, Copy)]
+pub struct FoundPoke {
+    // 128K RAM bank paged in at 0xC000 when the POKE was made, None for the fixed banks
+    pub bank: Option<u8>,
+    pub addr: u16,
+    pub value: u8,
+    pub original: u8,
+    // Written again every frame
+    pub frozen: bool,
+}
+
+#[derive(Default)]
+pub struct CheatFinder {
+    pub open: bool,
+    // RAM at the last search step, indexed from RAM_START
+    previous: Option<Vec<u8>>,
+    // Machine state shown in the panel (the game is paused while it is open)
+    current: Option<SnaImage>,
+    candidates: Vec<u16>,
+    cursor: usize,
+    top: usize,
+    // Number being typed in, and what it is for
+    pub entry: Option<(Entry, String)>,
+    pub pokes: Vec<FoundPoke>,
+    status: String,
+}
+
+fn ram(image: &SnaImage) -> Vec<u8> {
+    (RAM_START..=0xFFFF).map(|addr| image.read(addr)).collect()
+}
+
+impl CheatFinder {
+    pub fn refresh(&mut self, image: SnaImage) {
+        self.current = Some(image);
+    }
+
+    pub fn new_search(&mut self) {
+        let Some(image) = &self.current else { return };
+        self.previous = Some(ram(image));
+        self.candidates = (RAM_START..=0xFFFF).collect();
+        self.cursor = 0;
+        self.status = "NEW SEARCH".to_string();
+    }
+
+    pub fn narrow(&mut self, filter: Filter) {
+        let (Some(previous), Some(image)) = (&self.previous, &self.current) else { return };
+        let current = ram(image);
+        let index = |addr: u16| (addr - RAM_START) as usize;
+        self.candidates.retain(|&addr| {
+            let (before, now) = (previous[index(addr)], current[index(addr)]);
+            match filter {
+                Filter::Equal(value) => now == value,
+                Filter::Decreased => now < before,
+                Filter::Increased => now > before,
+            }
+        });
+        self.previous = Some(current);
+        self.cursor = self.cursor.min(self.candidates.len().saturating_sub(1));
+        self.status = match filter {
+            Filter::Equal(value) => format!("EQUAL TO {}", value),
+            Filter::Decreased => "DECREASED".to_string(),
+            Filter::Increased => "INCREASED".to_string(),
+        };
+    }
+
+    pub fn selected(&self) -> Option<u16> {
+        self.candidates.get(self.cursor).copied()
+    }
+
+    /// Current value of an address
+    pub fn value_at(&self, addr: u16) -> u8 {
+        self.current.as_ref().map(|image| image.read(addr)).unwrap_or(0)
+    }
+
+    pub fn move_cursor(&mut self, rows: i32) {
+        let last = self.candidates.len().saturating_sub(1) as i32;
+        self.cursor = (self.cursor as i32 + rows).clamp(0, last.max(0)) as usize;
+    }
+
+    /// Records a POKE (or replaces an earlier one at the same address)
+    pub fn add_poke(&mut self, addr: u16, value: u8, original: u8, frozen: bool) {
+        let bank = self.current.as_ref()
+            .and_then(|image| image.port_7ffd)
+            .filter(|_| addr >= 0xC000)
+            .map(|port| port & 0x07);
+        self.pokes.retain(|p| p.addr != addr);
+        self.pokes.push(FoundPoke { bank, addr, value, original, frozen });
+        self.status = format!("{} {}: {}", if frozen { "FROZEN" } else { "POKED" }, addr, value);
+    }
+
+    /// Unfreezes an address; false if it was not frozen
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        match self.pokes.iter_mut().find(|p| p.addr == addr && p.frozen) {
+            Some(poke) => {
+                poke.frozen = false;
+                self.status = format!("UNFROZEN {}", addr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn frozen(&self) -> impl Iterator<Item = &FoundPoke> {
+        self.pokes.iter().filter(|p| p.frozen)
+    }
+
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    /// Writes the POKEs made so far as a one-trainer .pok file
+    pub fn export(&self, path: Option<PathBuf>) -> Result<PathBuf> {
+        let path = path.context("No directory for the POK file")?;
+        let mut pok = String::from("NFound cheat\n");
+        for (i, poke) in self.pokes.iter().enumerate() {
+            let kind = if i + 1 == self.pokes.len() { 'Z' } else { 'M' };
+            let bank = poke.bank.unwrap_or(8);
+            pok.push_str(&format!("{} {} {} {} {}\n", kind, bank, poke.addr, poke.value, poke.original));
+        }
+        pok.push_str("Y\n");
+        std::fs::write(&path, pok).context("Failed to write POK file")?;
+        Ok(path)
+    }
+
+    /// Text lines of the panel with their colours, at most `rows` lines
+    pub fn lines(&mut self, rows: usize) -> Vec<(String, u32)> {
+        let visible = rows.saturating_sub(6).max(1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + visible {
+            self.top = self.cursor + 1 - visible;
+        }
+
+        let status = if self.previous.is_none() { "PRESS N TO START A SEARCH" } else { &self.status };
+        let mut lines = vec![
+            (format!("CHEAT FINDER - {} CANDIDATES", self.candidates.len()), COLOR_TITLE),
+            (status.to_string(), COLOR_TITLE),
+        ];
+        let index = |addr: u16| (addr - RAM_START) as usize;
+        for (i, &addr) in self.candidates.iter().enumerate().skip(self.top).take(visible) {
+            let now = self.value_at(addr);
+            let before = self.previous.as_ref().map(|p| p[index(addr)]).unwrap_or(0);
+            let frozen = if self.frozen().any(|p| p.addr == addr) { " FROZEN" } else { "" };
+            let color = if i == self.cursor { COLOR_SELECTED } else { COLOR_TEXT };
+            let marker = if i == self.cursor { '>' } else { ' ' };
+            lines.push((format!("{} {:5} ({:04X})  {:3}  WAS {:3}{}", marker, addr, addr, now, before, frozen), color));
+        }
+
+        lines.push((String::new(), COLOR_TEXT));
+        let entry = match &self.entry {
+            Some((Entry::Search, input)) => format!("SEARCH FOR VALUE: {}_", input),
+            Some((Entry::Poke, input)) => format!("POKE VALUE: {}_", input),
+            None => "0-9 ENTER: EQUAL TO  -: DECREASED  +: INCREASED".to_string(),
+        };
+        lines.push((entry, COLOR_TITLE));
+        lines.push(("N NEW  P POKE  F FREEZE  X EXPORT POK  ESC CLOSE".to_string(), COLOR_TITLE));
+        lines
+    }
+}
@@ -0,0 +1,51 @@
+use crate::pok::Trainer;
+
+pub const COLOR_TEXT: u32 = 0xFFCDCDCD;
+pub const COLOR_SELECTED: u32 = 0xFFFFFF00;
+pub const COLOR_TITLE: u32 = 0xFF00FFFF;
+
+/// OSD menu listing the POK trainers, each switched on or off on its own
+#[derive(Default)]
+pub struct CheatMenu {
+    pub open: bool,
+    cursor: usize,
+    // First trainer shown when the list is longer than the screen
+    top: usize,
+}
+
+impl CheatMenu {
+    pub fn selected(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn move_cursor(&mut self, down: bool, count: usize) {
+        if down {
+            self.cursor = (self.cursor + 1).min(count.saturating_sub(1));
+        } else {
+            self.cursor = self.cursor.saturating_sub(1);
+        }
+    }
+
+    /// Text lines of the menu with their colours, at most `rows` lines
+    pub fn lines(&mut self, trainers: &[Trainer], rows: usize) -> Vec<(String, u32)> {
+        let visible = rows.saturating_sub(3).max(1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + visible {
+            self.top = self.cursor + 1 - visible;
+        }
+
+        let mut lines = vec![(format!("CHEATS ({}/{})", self.cursor + 1, trainers.len()), COLOR_TITLE)];
+        for (i, trainer) in trainers.iter().enumerate().skip(self.top).take(visible) {
+            let marker = if i == self.cursor { '>' } else { ' ' };
+            let state = if trainer.enabled { "ON " } else { "OFF" };
+            let value = if trainer.needs_value() { " (VALUE)" } else { "" };
+            let frozen = if trainer.frozen { " (FROZEN)" } else { "" };
+            let color = if i == self.cursor { COLOR_SELECTED } else { COLOR_TEXT };
+            lines.push((format!("{} {} {}{}{}", marker, state, trainer.name, value, frozen), color));
+        }
+        lines.push((String::new(), COLOR_TEXT));
+        lines.push(("UP/DOWN SELECT  ENTER TOGGLE  F FREEZE  ESC CLOSE".to_string(), COLOR_TITLE));
+        lines
+    }
+}
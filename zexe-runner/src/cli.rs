@@ -0,0 +1,35 @@
+use anyhow::{Context, Result, anyhow};
+
+// Minimal command line helpers (the runner avoids a full argument parser to stay small)
+
+pub fn flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|a| a == name)
+}
+
+pub fn value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(|s| s.as_str())
+}
+
+/// All values of an option that may be repeated
+pub fn values<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    args.windows(2).filter(|w| w[0] == name).map(|w| w[1].as_str()).collect()
+}
+
+pub fn required<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    if flag(args, name) {
+        value(args, name).map(Some).with_context(|| format!("{} needs a value", name))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parses "0x5C00", "$5C00" or "23552"
+pub fn parse_u16(text: &str) -> Result<u16> {
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse::<u16>()
+    };
+    parsed.map_err(|_| anyhow!("Invalid number: {}", text))
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result, anyhow};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::Config;
+use crate::cli;
+use crate::paths;
+use crate::settings;
+
+// Configuration layers, each one overriding the keys it sets in the ones before it:
+//
+//   1. built-in defaults
+//   2. config embedded by the bundler
+//   3. config.json, then my_game.json, next to the executable
+//   4. the player's settings (saved at runtime in the user config directory)
+//   5. environment variables, ZEXE_<KEY> (e.g. ZEXE_VOLUME=80)
+//   6. command line flags (--volume 80, --set volume=80)
+//
+// Every value is checked against `Config` as it is applied. A bad value in a file is
+// skipped with a warning (on stderr and the OSD); a bad value in the environment or on
+// the command line is an error. config.schema.json describes the format.
+
+/// Current config format. Files without `config_version` are version 1.
+pub const CONFIG_VERSION: u32 = 2;
+
+// Version 1 matched these names by hand and silently used the fallback for anything
+// else; version 2 has strict, case-sensitive values
+const LEGACY_NAMES: &[(&str, &[&str], &str)] = &[
+    ("joystick", &["Off", "Kempston", "Sinclair1", "Sinclair2", "Cursor"], "Off"),
+    ("border", &["Full", "Minimal", "None"], "Full"),
+    ("filtering", &["Nearest", "Linear", "Scanlines", "Embedded", "Custom"], "Scanlines"),
+    ("ay_mode", &["ABC", "ACB", "Mono"], "ABC"),
+    ("ay_log_format", &["psg", "ym"], "psg"),
+];
+
+// Command line options that take a value, and the key they set
+const OPTIONS: &[(&str, &str)] = &[
+    ("--filter", "filtering"),
+    ("--joystick", "joystick"),
+    ("--border", "border"),
+    ("--volume", "volume"),
+    ("--ay-mode", "ay_mode"),
+    ("--latency", "audio_latency_ms"),
+    ("--audio-device", "audio_device"),
+    ("--sample-rate", "audio_sample_rate"),
+    ("--channels", "audio_channels"),
+    ("--screenshot-dir", "screenshot_dir"),
+    ("--machine", "machine"),
+    ("--rom", "rom"),
+];
+
+// Command line switches, the key they set and its value
+const SWITCHES: &[(&str, &str, bool)] = &[
+    ("--fullscreen", "fullscreen", true),
+    ("--windowed", "fullscreen", false),
+    ("--resume", "resume", true),
+    ("--no-resume", "resume", false),
+];
+
+/// Where a config value came from
+#[derive(Debug, Clone)]
+enum Source {
+    Default,
+    Embedded,
+    File(PathBuf),
+    Settings(PathBuf),
+    Env(String),
+    Cli(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Embedded => write!(f, "embedded config"),
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Settings(path) => write!(f, "player settings {}", path.display()),
+            Self::Env(var) => write!(f, "environment {}", var),
+            Self::Cli(flag) => write!(f, "command line {}", flag),
+        }
+    }
+}
+
+pub struct LayeredConfig {
+    values: Map<String, Value>,
+    sources: BTreeMap<String, Source>,
+    /// Short descriptions of skipped or migrated settings, for the OSD
+    pub problems: Vec<String>,
+}
+
+// Config files next to the executable, lowest precedence first
+fn exe_config_files() -> Vec<PathBuf> {
+    let Some(dir) = env::current_exe().ok().and_then(|p| p.parent().map(|d| d.to_path_buf())) else {
+        return Vec::new();
+    };
+    vec![dir.join("config.json"), dir.join(format!("{}.json", paths::exe_stem()))]
+}
+
+impl LayeredConfig {
+    pub fn load(embedded: Option<Vec<u8>>, args: &[String]) -> Result<Self> {
+        let Value::Object(values) = serde_json::to_value(Config::default())? else {
+            return Err(anyhow!("Default config is not an object"));
+        };
+        let sources = values.keys().map(|key| (key.clone(), Source::Default)).collect();
+        let mut layered = Self { values, sources, problems: Vec::new() };
+
+        if let Some(embedded) = embedded {
+            layered.merge_json(&embedded, Source::Embedded);
+        }
+        for path in exe_config_files() {
+            if let Ok(content) = std::fs::read(&path) {
+                layered.merge_json(&content, Source::File(path));
+            }
+        }
+        if let Some((path, player)) = settings::load() {
+            layered.merge(Value::Object(player), Source::Settings(path));
+        }
+
+        let keys: Vec<String> = layered.values.keys().cloned().collect();
+        for key in keys {
+            let var = format!("ZEXE_{}", key.to_uppercase());
+            if let Ok(text) = env::var(&var) {
+                layered.set_text(&key, &text, Source::Env(var.clone())).with_context(|| format!("Invalid {}", var))?;
+            }
+        }
+
+        for &(flag, key, on) in SWITCHES {
+            if cli::flag(args, flag) {
+                layered.set(key, Value::Bool(on), Source::Cli(flag.to_string()))?;
+            }
+        }
+        for &(flag, key) in OPTIONS {
+            if let Some(text) = cli::required(args, flag)? {
+                layered.set_text(key, text, Source::Cli(flag.to_string())).with_context(|| format!("Invalid {}", flag))?;
+            }
+        }
+        for setting in cli::values(args, "--set") {
+            let (key, text) = setting.split_once('=').context("--set needs KEY=VALUE")?;
+            layered.set_text(key.trim(), text, Source::Cli("--set".to_string())).context("Invalid --set")?;
+        }
+        Ok(layered)
+    }
+
+    fn merge_json(&mut self, data: &[u8], source: Source) {
+        match serde_json::from_slice(data) {
+            Ok(layer) => self.merge(layer, source),
+            Err(e) => self.problem(format!("Ignoring {}: {}", source, e), "CONFIG FILE"),
+        }
+    }
+
+    // A config file layer: keys that do not fit are skipped
+    fn merge(&mut self, layer: Value, source: Source) {
+        let Value::Object(mut layer) = layer else {
+            self.problem(format!("Ignoring {}: not a JSON object", source), "CONFIG FILE");
+            return;
+        };
+        // Editor hint for config.schema.json
+        layer.remove("$schema");
+        self.migrate(&mut layer, &source);
+        for (key, value) in layer {
+            if let Err(e) = self.set(&key, value, source.clone()) {
+                self.problem(format!("Ignoring {} from {}: {}", key, source, e), &key);
+            }
+        }
+    }
+
+    // Brings a layer written for an older format up to CONFIG_VERSION
+    fn migrate(&mut self, layer: &mut Map<String, Value>, source: &Source) {
+        let version = match layer.get("config_version") {
+            Some(version) => version.as_u64().unwrap_or(0) as u32,
+            None => 1,
+        };
+        if version > CONFIG_VERSION {
+            eprintln!("{} is for config version {}, this runner reads version {}", source, version, CONFIG_VERSION);
+        }
+        if version < 2 {
+            for &(key, names, fallback) in LEGACY_NAMES {
+                let Some(Value::String(name)) = layer.get(key) else { continue };
+                let migrated = match names.iter().find(|n| n.eq_ignore_ascii_case(name)) {
+                    Some(known) => known,
+                    None => {
+                        self.problem(format!("Unknown {} \"{}\" in {}, using {}", key, name, source, fallback), key);
+                        fallback
+                    }
+                };
+                layer.insert(key.to_string(), Value::from(migrated));
+            }
+        }
+        if version <= CONFIG_VERSION {
+            layer.insert("config_version".to_string(), Value::from(CONFIG_VERSION));
+        }
+    }
+
+    fn problem(&mut self, message: String, label: &str) {
+        eprintln!("{}", message);
+        self.problems.push(label.to_uppercase());
+    }
+
+    fn set(&mut self, key: &str, value: Value, source: Source) -> Result<()> {
+        if !self.values.contains_key(key) {
+            return Err(anyhow!("unknown setting {}", key));
+        }
+        let mut values = self.values.clone();
+        values.insert(key.to_string(), value);
+        serde_json::from_value::<Config>(Value::Object(values.clone())).map_err(|e| anyhow!("bad value for {}: {}", key, e))?;
+        self.values = values;
+        self.sources.insert(key.to_string(), source);
+        Ok(())
+    }
+
+    // Text from the environment or the command line: JSON if it fits, a string otherwise
+    fn set_text(&mut self, key: &str, text: &str, source: Source) -> Result<()> {
+        if let Ok(value) = serde_json::from_str(text)
+            && self.set(key, value, source.clone()).is_ok()
+        {
+            return Ok(());
+        }
+        self.set(key, Value::String(text.to_string()), source)
+    }
+
+    /// Whether a setting comes from a layer above the embedded config
+    pub fn overrides_embedded(&self, key: &str) -> bool {
+        !matches!(self.sources.get(key), None | Some(Source::Default | Source::Embedded))
+    }
+
+    /// Whether a setting comes from the environment or the command line, where paths are
+    /// relative to the working directory
+    pub fn set_by_invocation(&self, key: &str) -> bool {
+        matches!(self.sources.get(key), Some(Source::Env(_) | Source::Cli(_)))
+    }
+
+    pub fn config(&self) -> Config {
+        // Every layer was checked as it was applied
+        serde_json::from_value(Value::Object(self.values.clone())).unwrap_or_default()
+    }
+
+    /// --print-config: the effective value of every key and where it came from
+    pub fn print(&self) {
+        for (key, value) in &self.values {
+            let source = self.sources.get(key).unwrap_or(&Source::Default);
+            println!("{:<22} {:<28} {}", key, value.to_string(), source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BorderMode, JoystickMode};
+
+    // The defaults alone, without the files and environment `load` reads
+    fn defaults() -> LayeredConfig {
+        let Ok(Value::Object(values)) = serde_json::to_value(Config::default()) else { unreachable!() };
+        LayeredConfig { values, sources: BTreeMap::new(), problems: Vec::new() }
+    }
+
+    #[test]
+    fn migrates_version_1_names() {
+        let mut layered = defaults();
+        layered.merge_json(br#"{"joystick": "kempston", "border": "MINIMAL", "volume": 80}"#, Source::Embedded);
+        let config = layered.config();
+        assert_eq!(config.joystick, JoystickMode::Kempston);
+        assert_eq!(config.border, BorderMode::Minimal);
+        assert_eq!(config.volume, 80);
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert!(layered.problems.is_empty());
+    }
+
+    #[test]
+    fn version_1_falls_back_on_unknown_names() {
+        let mut layered = defaults();
+        layered.merge_json(br#"{"joystick": "Fuller"}"#, Source::Embedded);
+        assert_eq!(layered.config().joystick, JoystickMode::Off);
+        assert_eq!(layered.problems, vec!["JOYSTICK"]);
+    }
+
+    #[test]
+    fn version_2_is_strict() {
+        let mut layered = defaults();
+        layered.merge_json(br#"{"config_version": 2, "joystick": "kempston", "border": "Minimal"}"#, Source::Embedded);
+        let config = layered.config();
+        assert_eq!(config.joystick, JoystickMode::Off);
+        assert_eq!(config.border, BorderMode::Minimal);
+        assert_eq!(layered.problems, vec!["JOYSTICK"]);
+    }
+
+    #[test]
+    fn command_line_values() {
+        let mut layered = defaults();
+        layered.set_text("volume", "150", Source::Cli("--volume".to_string())).unwrap();
+        layered.set_text("joystick", "Cursor", Source::Cli("--joystick".to_string())).unwrap();
+        assert!(layered.set_text("joystick", "cursor", Source::Cli("--joystick".to_string())).is_err());
+        assert!(layered.set_text("no_such_key", "1", Source::Cli("--set".to_string())).is_err());
+        assert!(layered.set_by_invocation("volume"));
+        assert!(!layered.set_by_invocation("border"));
+        assert_eq!(layered.config().volume, 150);
+        assert_eq!(layered.config().joystick, JoystickMode::Cursor);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::disasm;
+use crate::sna::SnaImage;
+
+// Panel layout (lines of the 4x6 OSD font)
+const DISASM_LINES: usize = 12;
+const MEMORY_LINES: usize = 6;
+const MEMORY_ROW: u16 = 8;
+
+pub const COLOR_TEXT: u32 = 0xFFCDCDCD;
+pub const COLOR_PC: u32 = 0xFFFFFF00;
+pub const COLOR_CURSOR: u32 = 0xFF00FFFF;
+pub const COLOR_STATUS: u32 = 0xFFFF8080;
+
+/// Debugger overlay state. The machine is inspected through a saved SNA image,
+/// refreshed every time execution stops.
+#[derive(Default)]
+pub struct Debugger {
+    // Only available with --debug
+    pub enabled: bool,
+    pub paused: bool,
+    image: Option<SnaImage>,
+    // The ROM as loaded, which is not part of the saved state
+    pub rom: Vec<u8>,
+    disasm_top: u16,
+    cursor: usize,
+    memory_addr: u16,
+    // None follows the current paging, Some(n) shows RAM bank n
+    memory_bank: Option<usize>,
+    status: String,
+}
+
+impl Debugger {
+    /// Called whenever execution stops, with the machine state at that point
+    pub fn stopped(&mut self, image: SnaImage, status: &str) {
+        let pc = image.pc;
+        self.image = Some(image);
+        self.paused = true;
+        self.status = status.to_string();
+        if !self.visible_lines().contains(&pc) {
+            self.disasm_top = pc;
+        }
+        self.cursor = self.visible_lines().iter().position(|&a| a == pc).unwrap_or(0);
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn pc(&self) -> Option<u16> {
+        self.image.as_ref().map(|i| i.pc)
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        self.image.as_ref().map(|i| i.read_with_rom(&self.rom, addr)).unwrap_or(0xFF)
+    }
+
+    pub fn instruction_at(&self, addr: u16) -> disasm::Instruction {
+        disasm::disassemble(&|a| self.read(a), addr)
+    }
+
+    fn visible_lines(&self) -> Vec<u16> {
+        let mut lines = Vec::with_capacity(DISASM_LINES);
+        let mut addr = self.disasm_top;
+        for _ in 0..DISASM_LINES {
+            lines.push(addr);
+            addr = addr.wrapping_add(self.instruction_at(addr).length);
+        }
+        lines
+    }
+
+    pub fn cursor_addr(&self) -> u16 {
+        self.visible_lines()[self.cursor]
+    }
+
+    pub fn move_cursor(&mut self, down: bool) {
+        if down {
+            if self.cursor + 1 < DISASM_LINES {
+                self.cursor += 1;
+            } else {
+                self.disasm_top = self.visible_lines()[1];
+            }
+        } else if self.cursor > 0 {
+            self.cursor -= 1;
+        } else {
+            // Instructions cannot be decoded backwards reliably; step one byte
+            self.disasm_top = self.disasm_top.wrapping_sub(1);
+        }
+    }
+
+    pub fn scroll_memory(&mut self, rows: i32) {
+        self.memory_addr = self.memory_addr.wrapping_add((rows * MEMORY_ROW as i32) as u16);
+    }
+
+    pub fn show_memory_at(&mut self, addr: u16) {
+        self.memory_addr = addr & !(MEMORY_ROW - 1);
+    }
+
+    pub fn next_bank(&mut self) {
+        let banks = if self.image.as_ref().is_some_and(|i| i.is_128k()) { 8 } else { 0 };
+        self.memory_bank = match self.memory_bank {
+            None if banks > 0 => Some(0),
+            Some(n) if n + 1 < banks => Some(n + 1),
+            _ => None,
+        };
+    }
+
+    /// Text lines of the panel with their colours
+    pub fn panel_lines(&self, breakpoints: &[u16]) -> Vec<(String, u32)> {
+        let Some(image) = &self.image else { return Vec::new() };
+        let mut lines = Vec::new();
+        let h = &image.header;
+        let flags = h[21];
+        let flag_text: String = "SZ5H3PNC".chars().enumerate()
+            .map(|(i, c)| if flags & (0x80 >> i) != 0 { c } else { '-' })
+            .collect();
+
+        lines.push((format!("PC {:04X}  SP {:04X}  AF {:04X}  BC {:04X}", image.pc, image.reg16(23), image.reg16(21), image.reg16(13)), COLOR_TEXT));
+        lines.push((format!("HL {:04X}  DE {:04X}  IX {:04X}  IY {:04X}", image.reg16(9), image.reg16(11), image.reg16(17), image.reg16(15)), COLOR_TEXT));
+        lines.push((format!("AF'{:04X}  BC'{:04X}  DE'{:04X}  HL'{:04X}", image.reg16(7), image.reg16(5), image.reg16(3), image.reg16(1)), COLOR_TEXT));
+        let paging = image.port_7ffd.map(|p| format!("  7FFD {:02X}", p)).unwrap_or_default();
+        lines.push((
+            format!("I {:02X}  R {:02X}  IM {}  {}  {}{}", h[0], h[20], h[25], if h[19] & 0x04 != 0 { "EI" } else { "DI" }, flag_text, paging),
+            COLOR_TEXT,
+        ));
+        lines.push((self.status.clone(), COLOR_STATUS));
+
+        for (i, addr) in self.visible_lines().into_iter().enumerate() {
+            let marker = format!("{}{}", if breakpoints.contains(&addr) { '*' } else { ' ' }, if addr == image.pc { '>' } else { ' ' });
+            let instruction = self.instruction_at(addr);
+            let bytes: String = (0..instruction.length)
+                .map(|n| format!("{:02X}", self.read(addr.wrapping_add(n))))
+                .collect();
+            let text = format!("{} {:04X} {:<8} {}", marker, addr, bytes, instruction.text);
+            let color = if addr == image.pc { COLOR_PC } else if i == self.cursor { COLOR_CURSOR } else { COLOR_TEXT };
+            lines.push((text, color));
+        }
+
+        let bank_label = match self.memory_bank {
+            Some(n) => format!("BANK {}", n),
+            None => "PAGED".to_string(),
+        };
+        lines.push((format!("MEMORY ({})", bank_label), COLOR_STATUS));
+        for row in 0..MEMORY_LINES as u16 {
+            let addr = self.memory_addr.wrapping_add(row * MEMORY_ROW);
+            let bytes: Vec<u8> = (0..MEMORY_ROW)
+                .map(|n| {
+                    let a = addr.wrapping_add(n);
+                    match self.memory_bank {
+                        Some(bank) => image.banks[bank][a as usize & 0x3FFF],
+                        None => image.read_with_rom(&self.rom, a),
+                    }
+                })
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let shown_addr = if self.memory_bank.is_some() { addr & 0x3FFF } else { addr };
+            lines.push((format!("{:04X}  {}", shown_addr, hex.join(" ")), COLOR_TEXT));
+        }
+
+        lines.push(("S STEP  O OVER  R TO CURSOR  C CONTINUE".to_string(), COLOR_STATUS));
+        lines.push(("B BREAK  N BANK  M MEMORY  ARROWS/PGUP/PGDN".to_string(), COLOR_STATUS));
+        lines
+    }
+}
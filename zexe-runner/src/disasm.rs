@@ -0,0 +1,378 @@
+// Z80 disassembler for the debugger, including the undocumented IXH/IXL forms.
+// Opcodes are decoded with the usual x/y/z/p/q split of the opcode byte.
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SLL", "SRL"];
+const IM: [&str; 8] = ["0", "0", "1", "2", "0", "0", "1", "2"];
+const BLOCK: [[&str; 4]; 4] = [
+    ["LDI", "CPI", "INI", "OUTI"],
+    ["LDD", "CPD", "IND", "OUTD"],
+    ["LDIR", "CPIR", "INIR", "OTIR"],
+    ["LDDR", "CPDR", "INDR", "OTDR"],
+];
+const ACC_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+pub struct Instruction {
+    pub length: u16,
+    pub text: String,
+}
+
+impl Instruction {
+    /// Instructions that return to the next address; "step over" runs them to completion
+    pub fn steps_over(&self) -> bool {
+        let mnemonic = self.text.split(' ').next().unwrap_or("");
+        matches!(mnemonic, "CALL" | "RST" | "DJNZ" | "HALT" | "LDIR" | "LDDR" | "CPIR" | "CPDR" | "INIR" | "INDR" | "OTIR" | "OTDR")
+    }
+}
+
+struct Decoder<'a> {
+    read: &'a dyn Fn(u16) -> u8,
+    pos: u16,
+    // "IX" or "IY" after a DD/FD prefix
+    index: Option<&'static str>,
+    // Displacement of an indexed instruction, read before any immediate operand
+    displacement: Option<i8>,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> u8 {
+        let value = (self.read)(self.pos);
+        self.pos = self.pos.wrapping_add(1);
+        value
+    }
+
+    fn word(&mut self) -> u16 {
+        let lo = self.byte() as u16;
+        let hi = self.byte() as u16;
+        lo | (hi << 8)
+    }
+
+    fn relative(&mut self) -> String {
+        let offset = self.byte() as i8;
+        format!("{:04X}", self.pos.wrapping_add(offset as u16))
+    }
+
+    fn hl(&self) -> &'static str {
+        self.index.unwrap_or("HL")
+    }
+
+    fn indexed(&mut self) -> String {
+        let index = self.index.unwrap_or("HL");
+        let d = match self.displacement {
+            Some(d) => d,
+            None => {
+                let d = self.byte() as i8;
+                self.displacement = Some(d);
+                d
+            }
+        };
+        if d < 0 {
+            format!("({}-{:02X})", index, -(d as i16))
+        } else {
+            format!("({}+{:02X})", index, d)
+        }
+    }
+
+    // 8-bit register operand. With an index prefix (HL) becomes (IX+d), and H/L become
+    // IXH/IXL unless the instruction also accesses memory.
+    fn reg(&mut self, r: u8, memory_operand: bool) -> String {
+        match (r, self.index) {
+            (6, Some(_)) => self.indexed(),
+            (4, Some(index)) if !memory_operand => format!("{}H", index),
+            (5, Some(index)) if !memory_operand => format!("{}L", index),
+            _ => R[r as usize].to_string(),
+        }
+    }
+
+    fn rp(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP[p as usize] }
+    }
+
+    fn rp2(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP2[p as usize] }
+    }
+
+    fn main(&mut self, op: u8) -> String {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match x {
+            0 => match z {
+                0 => match y {
+                    0 => "NOP".to_string(),
+                    1 => "EX AF,AF'".to_string(),
+                    2 => format!("DJNZ {}", self.relative()),
+                    3 => format!("JR {}", self.relative()),
+                    _ => format!("JR {},{}", CC[y as usize - 4], self.relative()),
+                },
+                1 if q == 0 => format!("LD {},{:04X}", self.rp(p), self.word()),
+                1 => format!("ADD {},{}", self.hl(), self.rp(p)),
+                2 => match (q, p) {
+                    (0, 0) => "LD (BC),A".to_string(),
+                    (0, 1) => "LD (DE),A".to_string(),
+                    (0, 2) => format!("LD ({:04X}),{}", self.word(), self.hl()),
+                    (0, _) => format!("LD ({:04X}),A", self.word()),
+                    (_, 0) => "LD A,(BC)".to_string(),
+                    (_, 1) => "LD A,(DE)".to_string(),
+                    (_, 2) => format!("LD {},({:04X})", self.hl(), self.word()),
+                    _ => format!("LD A,({:04X})", self.word()),
+                },
+                3 => format!("{} {}", if q == 0 { "INC" } else { "DEC" }, self.rp(p)),
+                4 => format!("INC {}", self.reg(y, false)),
+                5 => format!("DEC {}", self.reg(y, false)),
+                6 => {
+                    let target = self.reg(y, false);
+                    format!("LD {},{:02X}", target, self.byte())
+                }
+                _ => ACC_OPS[y as usize].to_string(),
+            },
+            1 if y == 6 && z == 6 => "HALT".to_string(),
+            1 => {
+                let memory = y == 6 || z == 6;
+                let target = self.reg(y, memory);
+                let source = self.reg(z, memory);
+                format!("LD {},{}", target, source)
+            }
+            2 => format!("{}{}", ALU[y as usize], self.reg(z, false)),
+            _ => match z {
+                0 => format!("RET {}", CC[y as usize]),
+                1 if q == 0 => format!("POP {}", self.rp2(p)),
+                1 => match p {
+                    0 => "RET".to_string(),
+                    1 => "EXX".to_string(),
+                    2 => format!("JP ({})", self.hl()),
+                    _ => format!("LD SP,{}", self.hl()),
+                },
+                2 => format!("JP {},{:04X}", CC[y as usize], self.word()),
+                3 => match y {
+                    0 => format!("JP {:04X}", self.word()),
+                    2 => format!("OUT ({:02X}),A", self.byte()),
+                    3 => format!("IN A,({:02X})", self.byte()),
+                    4 => format!("EX (SP),{}", self.hl()),
+                    5 => "EX DE,HL".to_string(),
+                    6 => "DI".to_string(),
+                    7 => "EI".to_string(),
+                    _ => unreachable!("CB prefix is decoded separately"),
+                },
+                4 => format!("CALL {},{:04X}", CC[y as usize], self.word()),
+                5 if q == 0 => format!("PUSH {}", self.rp2(p)),
+                5 => format!("CALL {:04X}", self.word()),
+                6 => format!("{}{:02X}", ALU[y as usize], self.byte()),
+                _ => format!("RST {:02X}", y * 8),
+            },
+        }
+    }
+
+    fn cb(&mut self) -> String {
+        // Indexed form is DD CB d op: the displacement comes first
+        let memory = if self.index.is_some() { Some(self.indexed()) } else { None };
+        let op = self.byte();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let operand = match &memory {
+            Some(mem) if z == 6 => mem.clone(),
+            // Undocumented: the result is also copied to a register
+            Some(mem) => format!("{},{}", mem, R[z as usize]),
+            None => R[z as usize].to_string(),
+        };
+        match x {
+            0 => format!("{} {}", ROT[y as usize], operand),
+            1 => format!("BIT {},{}", y, operand),
+            2 => format!("RES {},{}", y, operand),
+            _ => format!("SET {},{}", y, operand),
+        }
+    }
+
+    fn ed(&mut self) -> String {
+        let op = self.byte();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) if y == 6 => "IN (C)".to_string(),
+            (1, 0) => format!("IN {},(C)", R[y as usize]),
+            (1, 1) if y == 6 => "OUT (C),0".to_string(),
+            (1, 1) => format!("OUT (C),{}", R[y as usize]),
+            (1, 2) => format!("{} HL,{}", if q == 0 { "SBC" } else { "ADC" }, RP[p as usize]),
+            (1, 3) if q == 0 => format!("LD ({:04X}),{}", self.word(), RP[p as usize]),
+            (1, 3) => format!("LD {},({:04X})", RP[p as usize], self.word()),
+            (1, 4) => "NEG".to_string(),
+            (1, 5) => if y == 1 { "RETI".to_string() } else { "RETN".to_string() },
+            (1, 6) => format!("IM {}", IM[y as usize]),
+            (1, _) => ["LD I,A", "LD R,A", "LD A,I", "LD A,R", "RRD", "RLD", "NOP", "NOP"][y as usize].to_string(),
+            (2, 0..=3) if y >= 4 => BLOCK[y as usize - 4][z as usize].to_string(),
+            _ => format!("DEFB ED,{:02X}", op),
+        }
+    }
+}
+
+/// Decodes the instruction at `addr`, reading memory through `read`
+pub fn disassemble(read: &dyn Fn(u16) -> u8, addr: u16) -> Instruction {
+    let mut decoder = Decoder { read, pos: addr, index: None, displacement: None };
+    let mut op = decoder.byte();
+    if op == 0xDD || op == 0xFD {
+        let next = read(decoder.pos);
+        // A prefix followed by another prefix acts as a lone NOP
+        if matches!(next, 0xDD | 0xED | 0xFD) {
+            return Instruction { length: 1, text: format!("DEFB {:02X}", op) };
+        }
+        decoder.index = Some(if op == 0xDD { "IX" } else { "IY" });
+        op = decoder.byte();
+    }
+    let text = match op {
+        0xCB => decoder.cb(),
+        0xED => decoder.ed(),
+        _ => decoder.main(op),
+    };
+    Instruction { length: decoder.pos.wrapping_sub(addr), text }
+}
+
+// Whether an unprefixed opcode uses (HL), which becomes (IX+d) with a prefix
+fn uses_hl_memory(op: u8) -> bool {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    match x {
+        0 => y == 6 && (4..=6).contains(&z),
+        1 => (y == 6 || z == 6) && op != 0x76,
+        2 => z == 6,
+        _ => false,
+    }
+}
+
+fn main_t_states(op: u8) -> (u32, u32) {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    let (p, q) = (y >> 1, y & 1);
+    let t = match x {
+        0 => match z {
+            0 => match y {
+                0 | 1 => 4,
+                2 => return (8, 13),
+                3 => 12,
+                _ => return (7, 12),
+            },
+            1 => if q == 0 { 10 } else { 11 },
+            2 => match p {
+                0 | 1 => 7,
+                2 => 16,
+                _ => 13,
+            },
+            3 => 6,
+            4 | 5 if y == 6 => 11,
+            6 if y == 6 => 10,
+            6 => 7,
+            _ => 4,
+        },
+        1 => if (y == 6 || z == 6) && op != 0x76 { 7 } else { 4 },
+        2 => if z == 6 { 7 } else { 4 },
+        _ => match z {
+            0 => return (5, 11),
+            1 if q == 0 => 10,
+            1 => [10, 4, 4, 6][p as usize],
+            2 => 10,
+            3 => [10, 0, 11, 11, 19, 4, 4, 4][y as usize],
+            4 => return (10, 17),
+            5 => if q == 0 { 11 } else { 17 },
+            6 => 7,
+            _ => 11,
+        },
+    };
+    (t, t)
+}
+
+fn ed_t_states(op: u8) -> (u32, u32) {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    let t = match (x, z) {
+        (1, 0) | (1, 1) => 12,
+        (1, 2) => 15,
+        (1, 3) => 20,
+        (1, 4) | (1, 6) => 8,
+        (1, 5) => 14,
+        (1, _) => [9, 9, 9, 9, 18, 18, 8, 8][y as usize],
+        (2, 0..=3) if y >= 6 => return (16, 21),
+        (2, 0..=3) if y >= 4 => 16,
+        _ => 8,
+    };
+    (t, t)
+}
+
+/// Uncontended T-states of the instruction at `addr`, as (not taken, taken) for
+/// conditional jumps, calls and returns, DJNZ and the repeating block instructions
+pub fn t_states(read: &dyn Fn(u16) -> u8, addr: u16) -> (u32, u32) {
+    let op = read(addr);
+    let next = read(addr.wrapping_add(1));
+    let t = match op {
+        0xCB if next & 7 != 6 => 8,
+        0xCB => if next & 0xC0 == 0x40 { 12 } else { 15 },
+        0xED => return ed_t_states(next),
+        0xDD | 0xFD => match next {
+            // A prefix followed by another prefix acts as a lone NOP
+            0xDD | 0xED | 0xFD => 4,
+            0xCB => if read(addr.wrapping_add(3)) & 0xC0 == 0x40 { 20 } else { 23 },
+            _ => {
+                let (not_taken, taken) = main_t_states(next);
+                let extra = match next {
+                    0x36 => 9,
+                    _ if uses_hl_memory(next) => 12,
+                    _ => 4,
+                };
+                return (not_taken + extra, taken + extra);
+            }
+        },
+        _ => return main_t_states(op),
+    };
+    (t, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> (String, u16) {
+        let read = |addr: u16| bytes.get(addr.wrapping_sub(0x8000) as usize).copied().unwrap_or(0);
+        let instruction = disassemble(&read, 0x8000);
+        (instruction.text, instruction.length)
+    }
+
+    #[test]
+    fn table_sample() {
+        let samples: &[(&[u8], &str)] = &[
+            (&[0x00], "NOP"),
+            (&[0x21, 0x00, 0x40], "LD HL,4000"),
+            (&[0x10, 0xFE], "DJNZ 8000"),
+            (&[0x76], "HALT"),
+            (&[0xCD, 0x34, 0x12], "CALL 1234"),
+            (&[0xD3, 0xFE], "OUT (FE),A"),
+            (&[0xCB, 0x47], "BIT 0,A"),
+            (&[0xED, 0xB0], "LDIR"),
+            (&[0xED, 0x45], "RETN"),
+            (&[0xED, 0x73, 0x00, 0x5C], "LD (5C00),SP"),
+            (&[0xDD, 0x7E, 0xFE], "LD A,(IX-02)"),
+            (&[0xDD, 0x66, 0x05], "LD H,(IX+05)"),
+            (&[0xDD, 0x26, 0x12], "LD IXH,12"),
+            (&[0xDD, 0x36, 0x05, 0xAA], "LD (IX+05),AA"),
+            (&[0xFD, 0xCB, 0x03, 0xC6], "SET 0,(IY+03)"),
+            (&[0xDD, 0xCB, 0x03, 0x00], "RLC (IX+03),B"),
+            (&[0xDD, 0xDD], "DEFB DD"),
+        ];
+        for &(bytes, text) in samples {
+            let length = if text.starts_with("DEFB") { 1 } else { bytes.len() as u16 };
+            assert_eq!(decode(bytes), (text.to_string(), length), "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn steps_over() {
+        let read = |_| 0xCD;
+        assert!(disassemble(&read, 0).steps_over());
+        let read = |_| 0xC3;
+        assert!(!disassemble(&read, 0).steps_over());
+    }
+
+    #[test]
+    fn conditional_t_states() {
+        let jr_nz = [0x20, 0x00];
+        assert_eq!(t_states(&|addr| jr_nz[addr as usize & 1], 0), (7, 12));
+        let ldir = [0xED, 0xB0];
+        assert_eq!(t_states(&|addr| ldir[addr as usize & 1], 0), (16, 21));
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{Context, Result};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::breakpoints::{Access, Watchpoint};
+use crate::sna::SnaImage;
+
+// GDB remote serial protocol server (--gdb PORT). A background thread accepts one
+// connection at a time and deals with packet framing only; the commands are run by
+// the App between frames (see App::poll_gdb), against the saved machine state.
+
+// GDB's Z80 register order: AF BC DE HL SP PC IX IY AF' BC' DE' HL' IR
+pub const REGISTER_COUNT: usize = 13;
+const PC: usize = 5;
+const IR: usize = 12;
+
+// SNA header offset of each 16-bit register (PC and IR are handled separately)
+fn register_offset(n: usize) -> Option<usize> {
+    [21, 13, 11, 9, 23, 0, 17, 15, 7, 5, 3, 1].get(n).copied().filter(|_| n != PC)
+}
+
+pub fn register(image: &SnaImage, n: usize) -> u16 {
+    match n {
+        PC => image.pc,
+        IR => (image.header[0] as u16) << 8 | image.header[20] as u16,
+        n => register_offset(n).map(|offset| image.reg16(offset)).unwrap_or(0),
+    }
+}
+
+pub fn set_register(image: &mut SnaImage, n: usize, value: u16) {
+    match n {
+        PC => image.pc = value,
+        IR => {
+            image.header[0] = (value >> 8) as u8;
+            image.header[20] = value as u8;
+        }
+        n => {
+            if let Some(offset) = register_offset(n) {
+                image.set_reg16(offset, value);
+            }
+        }
+    }
+}
+
+/// All registers as sent in a 'g' reply (16-bit little-endian each)
+pub fn registers_hex(image: &SnaImage) -> String {
+    (0..REGISTER_COUNT).map(|n| encode_hex(&register(image, n).to_le_bytes())).collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+pub enum Breakpoint {
+    Execute(u16),
+    Watch(Watchpoint),
+}
+
+pub enum Command {
+    Status,
+    ReadRegisters,
+    WriteRegisters(Vec<u16>),
+    ReadRegister(usize),
+    WriteRegister(usize, u16),
+    ReadMemory(u16, u16),
+    WriteMemory(u16, Vec<u8>),
+    InsertBreakpoint(Breakpoint),
+    RemoveBreakpoint(Breakpoint),
+    // Optional address to resume at
+    Continue(Option<u16>),
+    Step(Option<u16>),
+    Detach,
+    Kill,
+    // Answered without touching the machine (empty means unsupported)
+    Reply(String),
+}
+
+pub fn parse(packet: &str) -> Command {
+    let args = packet.get(1..).unwrap_or("");
+    parse_command(packet.chars().next().unwrap_or(' '), args).unwrap_or_else(|| Command::Reply("E01".to_string()))
+}
+
+fn parse_command(kind: char, args: &str) -> Option<Command> {
+    let command = match kind {
+        '?' => Command::Status,
+        'g' => Command::ReadRegisters,
+        'G' => {
+            let bytes = decode_hex(args)?;
+            Command::WriteRegisters(bytes.chunks_exact(2).map(|w| u16::from_le_bytes([w[0], w[1]])).collect())
+        }
+        'p' => Command::ReadRegister(usize::from_str_radix(args, 16).ok()?),
+        'P' => {
+            let (n, value) = args.split_once('=')?;
+            let mut bytes = decode_hex(value)?;
+            bytes.resize(2, 0);
+            Command::WriteRegister(usize::from_str_radix(n, 16).ok()?, u16::from_le_bytes([bytes[0], bytes[1]]))
+        }
+        'm' => {
+            let (addr, len) = args.split_once(',')?;
+            Command::ReadMemory(parse_hex(addr)?, parse_hex(len)?)
+        }
+        'M' => {
+            let (target, data) = args.split_once(':')?;
+            let (addr, _) = target.split_once(',')?;
+            Command::WriteMemory(parse_hex(addr)?, decode_hex(data)?)
+        }
+        'Z' | 'z' => {
+            let mut fields = args.split(',');
+            let (ty, addr, len) = (fields.next()?, parse_hex(fields.next()?)?, parse_hex(fields.next()?)?);
+            let access = match ty {
+                "0" | "1" => None,
+                "2" => Some(Access::Write),
+                "3" => Some(Access::Read),
+                "4" => Some(Access::ReadWrite),
+                _ => return Some(Command::Reply(String::new())),
+            };
+            let breakpoint = match access {
+                None => Breakpoint::Execute(addr),
+                Some(access) => Breakpoint::Watch(Watchpoint { start: addr, end: addr.wrapping_add(len.max(1) - 1), access }),
+            };
+            if kind == 'Z' { Command::InsertBreakpoint(breakpoint) } else { Command::RemoveBreakpoint(breakpoint) }
+        }
+        'c' => Command::Continue(parse_hex(args)),
+        's' => Command::Step(parse_hex(args)),
+        'D' => Command::Detach,
+        'k' => Command::Kill,
+        'H' | 'T' => Command::Reply("OK".to_string()),
+        'q' => Command::Reply(query(args).to_string()),
+        _ => Command::Reply(String::new()),
+    };
+    Some(command)
+}
+
+fn query(args: &str) -> &'static str {
+    match args.split(':').next().unwrap_or("") {
+        "Supported" => "PacketSize=1000",
+        "Attached" => "1",
+        "C" => "QC1",
+        "fThreadInfo" => "m1",
+        "sThreadInfo" => "l",
+        _ => "",
+    }
+}
+
+pub enum Event {
+    Connected,
+    Packet(String),
+    // Ctrl-C from the debugger
+    Interrupt,
+    Disconnected,
+}
+
+pub struct GdbServer {
+    events: Receiver<Event>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    // A continue or step waits for the machine to stop before replying
+    pub awaiting_stop: bool,
+}
+
+impl GdbServer {
+    /// Listens on localhost only: the protocol has no authentication
+    pub fn start(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).with_context(|| format!("Failed to listen on port {}", port))?;
+        let (sender, events) = mpsc::channel();
+        let stream = Arc::new(Mutex::new(None));
+        let slot = stream.clone();
+        std::thread::spawn(move || serve(listener, sender, slot));
+        println!("GDB server listening on 127.0.0.1:{}", port);
+        Ok(Self { events, stream, awaiting_stop: false })
+    }
+
+    pub fn poll(&self) -> Option<Event> {
+        self.events.try_recv().ok()
+    }
+
+    pub fn send(&self, payload: &str) {
+        let checksum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        send_raw(&self.stream, format!("${}#{:02x}", payload, checksum).as_bytes());
+    }
+}
+
+fn send_raw(slot: &Mutex<Option<TcpStream>>, data: &[u8]) {
+    if let Some(stream) = slot.lock().unwrap().as_mut() {
+        let _ = stream.write_all(data);
+    }
+}
+
+fn serve(listener: TcpListener, events: Sender<Event>, slot: Arc<Mutex<Option<TcpStream>>>) {
+    for stream in listener.incoming().flatten() {
+        let Ok(writer) = stream.try_clone() else { continue };
+        let _ = stream.set_nodelay(true);
+        *slot.lock().unwrap() = Some(writer);
+        if events.send(Event::Connected).is_err() {
+            return;
+        }
+        let _ = read_packets(stream, &events, &slot);
+        *slot.lock().unwrap() = None;
+        if events.send(Event::Disconnected).is_err() {
+            return;
+        }
+    }
+}
+
+fn next_byte(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> io::Result<u8> {
+    bytes.next().unwrap_or_else(|| Err(io::ErrorKind::UnexpectedEof.into()))
+}
+
+// Reads "$payload#checksum" packets and Ctrl-C bytes until the connection closes
+fn read_packets(stream: TcpStream, events: &Sender<Event>, slot: &Mutex<Option<TcpStream>>) -> io::Result<()> {
+    let mut bytes = BufReader::new(stream).bytes();
+    while let Some(byte) = bytes.next() {
+        match byte? {
+            0x03 => {
+                let _ = events.send(Event::Interrupt);
+            }
+            b'$' => {
+                let mut payload = Vec::new();
+                loop {
+                    match next_byte(&mut bytes)? {
+                        b'#' => break,
+                        b => payload.push(b),
+                    }
+                }
+                let checksum = [next_byte(&mut bytes)?, next_byte(&mut bytes)?];
+                let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+                if expected == Some(payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))) {
+                    send_raw(slot, b"+");
+                    let _ = events.send(Event::Packet(String::from_utf8_lossy(&payload).into_owned()));
+                } else {
+                    send_raw(slot, b"-");
+                }
+            }
+            // Acknowledgements of our replies
+            _ => {}
+        }
+    }
+    Ok(())
+}
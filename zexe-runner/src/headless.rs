@@ -0,0 +1,210 @@
+use anyhow::{Context, Result, anyhow};
+use rustzx_core::EmulationStopReason;
+use rustzx_core::zx::joy::kempston::KempstonKey;
+use rustzx_core::zx::keys::ZXKey;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cli;
+use crate::host::{self, DebugHit};
+use crate::input::{self, InputState};
+use crate::png;
+use crate::sna::SnaImage;
+use crate::trace::{TraceOptions, Tracer};
+
+// Runs the bundled game without window or audio, for automated smoke tests:
+//
+//   my_game --headless --frames 500 --keys 100:ENTER --keys 300:SPACE:10
+//           --until-pc 0x8000 --screenshot out.png --dump-ram out.bin
+//           --trace trace.txt.gz --trace-frames 100-101
+//
+// The process fails if an --until-* condition was given and not met.
+
+const DEFAULT_FRAMES: u32 = 250;
+const DEFAULT_PRESS_FRAMES: u32 = 5;
+
+enum ScriptKey {
+    Zx(ZXKey),
+    Kempston(KempstonKey),
+}
+
+// Keys held down from `frame` for `duration` frames
+struct KeyPress {
+    frame: u32,
+    duration: u32,
+    keys: Vec<ScriptKey>,
+}
+
+impl KeyPress {
+    // "<frame>:<KEY>[+<KEY>...][:<duration>]", e.g. "100:ENTER" or "250:SHIFT+0:10"
+    fn parse(text: &str) -> Result<Self> {
+        let mut parts = text.split(':');
+        let frame = parts.next().unwrap_or_default().trim().parse::<u32>()
+            .with_context(|| format!("Invalid frame in key press '{}'", text))?;
+        let names = parts.next().with_context(|| format!("Missing key in key press '{}'", text))?;
+        let duration = match parts.next() {
+            Some(d) => d.trim().parse::<u32>().with_context(|| format!("Invalid duration in key press '{}'", text))?,
+            None => DEFAULT_PRESS_FRAMES,
+        };
+
+        let mut keys = Vec::new();
+        for name in names.split('+') {
+            if let Some(key) = input::zx_key_from_name(name) {
+                keys.push(ScriptKey::Zx(key));
+            } else if let Some(key) = input::kempston_key_from_name(name) {
+                keys.push(ScriptKey::Kempston(key));
+            } else {
+                return Err(anyhow!("Unknown key '{}'", name));
+            }
+        }
+        Ok(Self { frame, duration, keys })
+    }
+
+    fn is_held(&self, frame: u32) -> bool {
+        frame >= self.frame && frame - self.frame < self.duration
+    }
+}
+
+pub struct HeadlessOptions {
+    frames: u32,
+    until_pc: Option<u16>,
+    until_mem: Option<(u16, u8)>,
+    presses: Vec<KeyPress>,
+    screenshot: Option<PathBuf>,
+    ram_dump: Option<PathBuf>,
+    trace: Option<TraceOptions>,
+}
+
+impl HeadlessOptions {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let frames = match cli::required(args, "--frames")? {
+            Some(n) => n.parse::<u32>().context("Invalid --frames value")?,
+            None => DEFAULT_FRAMES,
+        };
+        let until_pc = cli::required(args, "--until-pc")?.map(cli::parse_u16).transpose()?;
+        let until_mem = match cli::required(args, "--until-mem")? {
+            // "<addr>=<value>"
+            Some(cond) => {
+                let (addr, value) = cond.split_once('=').context("--until-mem expects ADDR=VALUE")?;
+                let value = cli::parse_u16(value)?;
+                Some((cli::parse_u16(addr)?, u8::try_from(value).context("--until-mem value must be a byte")?))
+            }
+            None => None,
+        };
+        let presses = cli::values(args, "--keys").into_iter()
+            .flat_map(|v| v.split(','))
+            .map(KeyPress::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            frames,
+            until_pc,
+            until_mem,
+            presses,
+            screenshot: cli::required(args, "--screenshot")?.map(PathBuf::from),
+            ram_dump: cli::required(args, "--dump-ram")?.map(PathBuf::from),
+            trace: TraceOptions::from_args(args)?,
+        })
+    }
+
+    fn has_condition(&self) -> bool {
+        self.until_pc.is_some() || self.until_mem.is_some()
+    }
+}
+
+pub fn run(snapshot_data: &[u8], machine: &crate::MachineSettings, options: &HeadlessOptions) -> Result<()> {
+    let (mut emulator, loaded_data, hooks) = crate::create_emulator(snapshot_data, &crate::SoundSettings::silent(), machine)?;
+    if !snapshot_data.is_empty() {
+        crate::load_state(&mut emulator, &loaded_data)?;
+    }
+    if let Some(pc) = options.until_pc {
+        hooks.debug.borrow_mut().breakpoints.push(pc);
+    }
+
+    let mut tracer = match options.trace.clone() {
+        Some(trace) => {
+            let is_128k = crate::save_state(&mut emulator).and_then(|state| SnaImage::parse(&state)).is_ok_and(|image| image.is_128k());
+            Some(Tracer::start(trace, crate::rom_image(machine, is_128k)?)?)
+        }
+        None => None,
+    };
+
+    let mut input = InputState::default();
+    let mut reached = None;
+    let mut frame = 0;
+
+    while frame < options.frames {
+        let mut target = InputState::default();
+        for press in options.presses.iter().filter(|p| p.is_held(frame)) {
+            for key in &press.keys {
+                match key {
+                    ScriptKey::Zx(k) => target.set_key(*k, true),
+                    ScriptKey::Kempston(k) => target.set_kempston(*k, true),
+                }
+            }
+        }
+        target.apply(&input, &mut emulator);
+        input = target;
+
+        if let Some(tracer) = &mut tracer {
+            tracer.begin_frame();
+            hooks.debug.borrow_mut().tracing = tracer.wants_frame();
+        }
+        let result = loop {
+            let result = emulator.emulate_frames(Duration::from_millis(20));
+            let stopped = matches!(result, Ok(EmulationStopReason::Breakpoint));
+            if let Some(tracer) = &mut tracer
+                && stopped && hooks.debug.borrow().tracing {
+                    let image = SnaImage::parse(&crate::save_state(&mut emulator)?)?;
+                    tracer.log(&image)?;
+                    let mut debug = hooks.debug.borrow_mut();
+                    debug.tracing = !tracer.is_done();
+                    // Stopped only to trace: let the instruction run
+                    if matches!(debug.hit, Some(DebugHit::Trace)) {
+                        debug.resume_at = Some(image.pc);
+                        continue;
+                    }
+            }
+            break result;
+        };
+        frame += 1;
+
+        if matches!(result, Ok(EmulationStopReason::Breakpoint)) {
+            reached = Some(format!("PC reached {:04X}", options.until_pc.unwrap_or(0)));
+            break;
+        }
+        if let Some((addr, value)) = options.until_mem {
+            let image = SnaImage::parse(&crate::save_state(&mut emulator)?)?;
+            if image.read(addr) == value {
+                reached = Some(format!("({:04X}) = {:02X}", addr, value));
+                break;
+            }
+        }
+    }
+
+    if let Some(tracer) = tracer {
+        println!("Trace written to {:?}", tracer.finish()?);
+    }
+    if let Some(path) = &options.screenshot {
+        let pixels = host::compose_frame(emulator.screen_buffer().get_buffer(), emulator.border_buffer().get_buffer());
+        png::save_png(path, &pixels, 320, 240).context("Failed to write screenshot")?;
+        println!("Screenshot written to {:?}", path);
+    }
+    if let Some(path) = &options.ram_dump {
+        let image = SnaImage::parse(&crate::save_state(&mut emulator)?)?;
+        std::fs::write(path, image.ram_dump()).context("Failed to write RAM dump")?;
+        println!("RAM written to {:?}", path);
+    }
+
+    match reached {
+        Some(condition) => {
+            println!("Condition met after {} frames: {}", frame, condition);
+            Ok(())
+        }
+        None if options.has_condition() => Err(anyhow!("Condition not met after {} frames", frame)),
+        None => {
+            println!("Emulated {} frames", frame);
+            Ok(())
+        }
+    }
+}
@@ -1,8 +1,14 @@
+use rustzx_core::error::IoError;
+use rustzx_core::Emulator;
 use rustzx_core::host::{
-    FrameBuffer, FrameBufferSource, Host, HostContext, StubDebugInterface, StubIoExtender,
-    Stopwatch as StopwatchTrait
+    BufferCursor, DataRecorder, DebugInterface, FrameBuffer, FrameBufferSource, Host, HostContext,
+    RomFormat, RomSet, StubIoExtender, Stopwatch as StopwatchTrait
 };
 use rustzx_core::zx::video::colors::{ZXBrightness, ZXColor};
+use crate::breakpoints::{self, MemoryRead, PortAccess, PortBreakpoint, Watchpoint};
+use crate::sna::SnaImage;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 // --- Stopwatch ---
@@ -55,6 +61,16 @@ impl FrameBuffer for EmulatorFrameBuffer {
     }
 }
 
+/// Full 320x240 frame: border with the 256x192 screen placed at (32, 24)
+pub fn compose_frame(screen: &[u32], border: &[u32]) -> Vec<u32> {
+    let mut frame = border.to_vec();
+    frame.resize(320 * 240, 0xFF000000);
+    for (y, row) in screen.chunks_exact(256).take(192).enumerate() {
+        let start = (y + 24) * 320 + 32;
+        frame[start..start + 256].copy_from_slice(row);
+    }
+    frame
+}
 
 fn zx_color_to_u32(color: ZXColor, brightness: ZXBrightness) -> u32 {
     let bright = match brightness {
@@ -81,6 +97,213 @@ fn zx_color_to_u32(color: ZXColor, brightness: ZXBrightness) -> u32 {
     }
 }
 
+// --- Snapshot Recorder ---
+// Collects a saved snapshot into an in-memory buffer
+pub struct BufferRecorder<'a> {
+    buffer: &'a mut Vec<u8>,
+}
+
+impl<'a> BufferRecorder<'a> {
+    pub fn new(buffer: &'a mut Vec<u8>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl DataRecorder for BufferRecorder<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+// --- Debug Interface ---
+// State shared between the emulator's debug hook and the App
+#[derive(Default)]
+pub struct DebugState {
+    // Instructions executed since last reset (checked against input recordings to spot desyncs)
+    pub instruction_count: u32,
+    // Execution stops before running an instruction at one of these addresses
+    pub breakpoints: Vec<u16>,
+    // One-shot breakpoint (run to cursor, step over)
+    pub temp_breakpoint: Option<u16>,
+    // Stop after this many more instructions (single step)
+    pub stop_after: Option<u32>,
+    // Lets the instruction execution stopped at run when resuming
+    pub resume_at: Option<u16>,
+    // Read watchpoints and port breakpoints, checked by decoding each instruction
+    pub read_watches: Vec<Watchpoint>,
+    pub port_breaks: Vec<PortBreakpoint>,
+    // Memory as of the start of the frame, and the ROM, used for that decoding
+    pub memory: Option<SnaImage>,
+    pub rom: Vec<u8>,
+    // Why the hook last stopped execution
+    pub hit: Option<DebugHit>,
+    // Replaying a frame to find a write: only `stop_after` applies
+    pub replaying: bool,
+    // Stop before every instruction so it can be traced
+    pub tracing: bool,
+    // Stop before OUTs that may write to the AY, while an AY log records
+    pub ay_taps: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DebugHit {
+    Breakpoint,
+    Step,
+    // Read that may hit a watched address (register-based ones are checked against the registers)
+    Read(MemoryRead),
+    // Port access that may match a port breakpoint (checked against the registers)
+    Port(PortAccess),
+    Trace,
+}
+
+impl DebugState {
+    fn check(&mut self, addr: u16) -> Option<DebugHit> {
+        if let Some(remaining) = self.stop_after {
+            if remaining == 0 {
+                self.stop_after = None;
+                return Some(DebugHit::Step);
+            }
+            self.stop_after = Some(remaining - 1);
+        }
+        if self.resume_at.take() == Some(addr) || self.replaying {
+            return None;
+        }
+        if self.temp_breakpoint == Some(addr) {
+            self.temp_breakpoint = None;
+            return Some(DebugHit::Step);
+        }
+        if self.breakpoints.contains(&addr) {
+            return Some(DebugHit::Breakpoint);
+        }
+        self.check_instruction(addr).or(self.tracing.then_some(DebugHit::Trace))
+    }
+
+    fn check_instruction(&self, addr: u16) -> Option<DebugHit> {
+        if self.read_watches.is_empty() && self.port_breaks.is_empty() && !self.ay_taps {
+            return None;
+        }
+        let memory = self.memory.as_ref()?;
+        let bytes = [0, 1, 2, 3].map(|i| memory.read_with_rom(&self.rom, addr.wrapping_add(i)));
+        match breakpoints::memory_read(bytes) {
+            Some(read @ MemoryRead::Absolute { addr, size })
+                if (0..size).any(|i| self.read_watches.iter().any(|w| w.contains(addr.wrapping_add(i)))) =>
+            {
+                return Some(DebugHit::Read(read));
+            }
+            Some(MemoryRead::Absolute { .. }) => {}
+            Some(read) if !self.read_watches.is_empty() => return Some(DebugHit::Read(read)),
+            _ => {}
+        }
+        match breakpoints::port_access(bytes)? {
+            // Both AY ports have bit 1 of the low byte clear
+            access @ PortAccess::Immediate { low, write } => {
+                let ay = self.ay_taps && write && low & 0x02 == 0;
+                (ay || self.port_breaks.iter().any(|p| p.may_match_low(low, write))).then_some(DebugHit::Port(access))
+            }
+            access @ PortAccess::ViaBc { write, .. } => {
+                let ay = self.ay_taps && write;
+                (ay || self.port_breaks.iter().any(|p| if write { p.access.writes() } else { p.access.reads() })).then_some(DebugHit::Port(access))
+            }
+        }
+    }
+}
+
+pub type SharedDebugState = Rc<RefCell<DebugState>>;
+
+pub struct AppDebugInterface {
+    state: SharedDebugState,
+}
+
+impl AppDebugInterface {
+    pub fn new(state: SharedDebugState) -> Self {
+        Self { state }
+    }
+}
+
+impl DebugInterface for AppDebugInterface {
+    // Called before every instruction
+    fn check_pc_breakpoint(&mut self, addr: u16) -> bool {
+        let mut state = self.state.borrow_mut();
+        let hit = state.check(addr);
+        state.hit = hit;
+        // A stopped instruction is checked again when execution resumes
+        if hit.is_none() {
+            state.instruction_count += 1;
+        }
+        hit.is_some()
+    }
+}
+
+// --- AY Tap ---
+// The core's AY ports are left alone: while an AY log records, the debug hook stops on
+// OUT instructions that may reach the chip and the App reads the write from the registers
+
+// State shared between the AY tap and the App
+#[derive(Default)]
+pub struct IoState {
+    // Only machines with the chip can log it
+    pub ay_ports: bool,
+    pub ay_register: u8,
+    // Registers as dumped when logging started, then as written by the program
+    pub ay_registers: [u8; 16],
+    // Register writes since the last frame
+    pub ay_writes: Vec<(u8, u8)>,
+}
+
+impl IoState {
+    /// Follows a port write made while logging
+    pub fn record_out(&mut self, port: u16, data: u8) {
+        if port & 0xC002 == 0xC000 {
+            self.ay_register = data & 0x0F;
+        } else if port & 0xC002 == 0x8000 {
+            let register = self.ay_register;
+            self.ay_registers[register as usize] = data;
+            self.ay_writes.push((register, data));
+        }
+    }
+}
+
+pub type SharedIoState = Rc<RefCell<IoState>>;
+
+// --- Hooks ---
+/// Shared state behind the hooks installed into every emulator instance
+#[derive(Clone, Default)]
+pub struct HostHooks {
+    pub debug: SharedDebugState,
+    pub io: SharedIoState,
+}
+
+impl HostHooks {
+    pub fn install(&self, emulator: &mut Emulator<AppHost>) {
+        emulator.set_debug_interface(AppDebugInterface::new(self.debug.clone()));
+    }
+}
+
+// --- Custom ROM ---
+/// ROM image handed to the core in 16K pages
+pub struct RomPages {
+    pages: Vec<Vec<u8>>,
+}
+
+impl RomPages {
+    pub fn new(rom: &[u8]) -> Self {
+        Self { pages: rom.chunks(0x4000).map(|page| page.to_vec()).collect() }
+    }
+}
+
+impl RomSet for RomPages {
+    type Asset = BufferCursor<Vec<u8>>;
+
+    fn format(&self) -> RomFormat {
+        RomFormat::Binary16KPages
+    }
+
+    fn next_asset(&mut self) -> Option<Self::Asset> {
+        (!self.pages.is_empty()).then(|| BufferCursor::new(self.pages.remove(0)))
+    }
+}
+
 // --- Host Implementation ---
 pub struct AppHost;
 
@@ -90,7 +313,7 @@ impl Host for AppHost {
     type FrameBuffer = EmulatorFrameBuffer;
     type EmulationStopwatch = Stopwatch;
     type IoExtender = StubIoExtender;
-    type DebugInterface = StubDebugInterface;
+    type DebugInterface = AppDebugInterface;
 }
 
 impl HostContext<AppHost> for () {
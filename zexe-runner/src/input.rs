@@ -0,0 +1,161 @@
+use rustzx_core::Emulator;
+use rustzx_core::zx::keys::ZXKey;
+use rustzx_core::zx::joy::kempston::KempstonKey;
+use rustzx_core::zx::joy::sinclair::{SinclairKey, SinclairJoyNum};
+
+use crate::host::AppHost;
+
+pub const INPUT_STATE_SIZE: usize = 9;
+
+// Keyboard matrix in port 0xFE order: 8 half-rows of 5 keys (bit 0 first)
+const KEY_MATRIX: [[ZXKey; 5]; 8] = [
+    [ZXKey::Shift, ZXKey::Z, ZXKey::X, ZXKey::C, ZXKey::V],
+    [ZXKey::A, ZXKey::S, ZXKey::D, ZXKey::F, ZXKey::G],
+    [ZXKey::Q, ZXKey::W, ZXKey::E, ZXKey::R, ZXKey::T],
+    [ZXKey::N1, ZXKey::N2, ZXKey::N3, ZXKey::N4, ZXKey::N5],
+    [ZXKey::N0, ZXKey::N9, ZXKey::N8, ZXKey::N7, ZXKey::N6],
+    [ZXKey::P, ZXKey::O, ZXKey::I, ZXKey::U, ZXKey::Y],
+    [ZXKey::Enter, ZXKey::L, ZXKey::K, ZXKey::J, ZXKey::H],
+    [ZXKey::Space, ZXKey::SymShift, ZXKey::M, ZXKey::N, ZXKey::B],
+];
+
+const KEMPSTON_KEYS: [KempstonKey; 5] = [
+    KempstonKey::Right, KempstonKey::Left, KempstonKey::Down, KempstonKey::Up, KempstonKey::Fire,
+];
+
+fn key_position(key: ZXKey) -> (usize, usize) {
+    for (row, keys) in KEY_MATRIX.iter().enumerate() {
+        if let Some(bit) = keys.iter().position(|k| *k == key) {
+            return (row, bit);
+        }
+    }
+    unreachable!("every ZXKey is in the matrix")
+}
+
+// Sinclair joysticks are wired to number keys: joystick 1 = 6-0, joystick 2 = 1-5
+pub fn sinclair_to_key(num: SinclairJoyNum, key: SinclairKey) -> ZXKey {
+    match (num, key) {
+        (SinclairJoyNum::Fist, SinclairKey::Left) => ZXKey::N6,
+        (SinclairJoyNum::Fist, SinclairKey::Right) => ZXKey::N7,
+        (SinclairJoyNum::Fist, SinclairKey::Down) => ZXKey::N8,
+        (SinclairJoyNum::Fist, SinclairKey::Up) => ZXKey::N9,
+        (SinclairJoyNum::Fist, SinclairKey::Fire) => ZXKey::N0,
+        (SinclairJoyNum::Second, SinclairKey::Left) => ZXKey::N1,
+        (SinclairJoyNum::Second, SinclairKey::Right) => ZXKey::N2,
+        (SinclairJoyNum::Second, SinclairKey::Down) => ZXKey::N3,
+        (SinclairJoyNum::Second, SinclairKey::Up) => ZXKey::N4,
+        (SinclairJoyNum::Second, SinclairKey::Fire) => ZXKey::N5,
+    }
+}
+
+/// Snapshot of everything the player is holding down: the 40 keys and the Kempston port.
+/// Bits are set for pressed keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputState {
+    rows: [u8; 8],
+    kempston: u8,
+}
+
+impl InputState {
+    pub fn set_key(&mut self, key: ZXKey, pressed: bool) {
+        let (row, bit) = key_position(key);
+        if pressed {
+            self.rows[row] |= 1 << bit;
+        } else {
+            self.rows[row] &= !(1 << bit);
+        }
+    }
+
+    pub fn set_kempston(&mut self, key: KempstonKey, pressed: bool) {
+        let bit = KEMPSTON_KEYS.iter().position(|k| *k == key).unwrap_or(0);
+        if pressed {
+            self.kempston |= 1 << bit;
+        } else {
+            self.kempston &= !(1 << bit);
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; INPUT_STATE_SIZE] {
+        let mut bytes = [0u8; INPUT_STATE_SIZE];
+        bytes[..8].copy_from_slice(&self.rows);
+        bytes[8] = self.kempston;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < INPUT_STATE_SIZE {
+            return None;
+        }
+        let mut rows = [0u8; 8];
+        rows.copy_from_slice(&bytes[..8]);
+        Some(Self { rows, kempston: bytes[8] })
+    }
+
+    /// Sends the presses/releases needed to go from `current` to this state
+    pub fn apply(&self, current: &InputState, emulator: &mut Emulator<AppHost>) {
+        for (row, keys) in KEY_MATRIX.iter().enumerate() {
+            let changed = self.rows[row] ^ current.rows[row];
+            for (bit, key) in keys.iter().enumerate() {
+                if changed & (1 << bit) != 0 {
+                    emulator.send_key(*key, self.rows[row] & (1 << bit) != 0);
+                }
+            }
+        }
+        let changed = self.kempston ^ current.kempston;
+        for (bit, key) in KEMPSTON_KEYS.iter().enumerate() {
+            if changed & (1 << bit) != 0 {
+                emulator.send_kempston_key(*key, self.kempston & (1 << bit) != 0);
+            }
+        }
+    }
+}
+
+// Names for KEY_MATRIX entries
+const KEY_NAMES: [[&str; 5]; 8] = [
+    ["SHIFT", "Z", "X", "C", "V"],
+    ["A", "S", "D", "F", "G"],
+    ["Q", "W", "E", "R", "T"],
+    ["1", "2", "3", "4", "5"],
+    ["0", "9", "8", "7", "6"],
+    ["P", "O", "I", "U", "Y"],
+    ["ENTER", "L", "K", "J", "H"],
+    ["SPACE", "SYMSHIFT", "M", "N", "B"],
+];
+
+/// Parses a ZX key name: "A"-"Z", "0"-"9", "ENTER", "SPACE", "SHIFT" (Caps Shift), "SYMSHIFT"
+pub fn zx_key_from_name(name: &str) -> Option<ZXKey> {
+    let name = match name.trim().to_ascii_uppercase().as_str() {
+        "RETURN" => "ENTER".to_string(),
+        "CAPS" | "CAPSSHIFT" => "SHIFT".to_string(),
+        "SYM" | "SYMBOLSHIFT" => "SYMSHIFT".to_string(),
+        other => other.to_string(),
+    };
+    for (row, names) in KEY_NAMES.iter().enumerate() {
+        if let Some(bit) = names.iter().position(|n| *n == name) {
+            return Some(KEY_MATRIX[row][bit]);
+        }
+    }
+    None
+}
+
+/// Name of a ZX key as accepted by `zx_key_from_name`
+pub fn zx_key_name(key: ZXKey) -> &'static str {
+    for (row, keys) in KEY_MATRIX.iter().enumerate() {
+        if let Some(bit) = keys.iter().position(|&k| k == key) {
+            return KEY_NAMES[row][bit];
+        }
+    }
+    "?"
+}
+
+/// Parses a Kempston direction name: "KEMPSTON_UP", "KEMPSTON_FIRE", ...
+pub fn kempston_key_from_name(name: &str) -> Option<KempstonKey> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "KEMPSTON_UP" => Some(KempstonKey::Up),
+        "KEMPSTON_DOWN" => Some(KempstonKey::Down),
+        "KEMPSTON_LEFT" => Some(KempstonKey::Left),
+        "KEMPSTON_RIGHT" => Some(KempstonKey::Right),
+        "KEMPSTON_FIRE" => Some(KempstonKey::Fire),
+        _ => None,
+    }
+}
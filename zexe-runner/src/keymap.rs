@@ -0,0 +1,309 @@
+use rustzx_core::zx::joy::kempston::KempstonKey;
+use rustzx_core::zx::joy::sinclair::SinclairKey;
+use rustzx_core::zx::keys::ZXKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use winit::keyboard::KeyCode;
+
+use crate::input;
+
+// Host key bindings: emulator hotkeys, the joystick and the ZX keyboard. The `keymap`
+// config section changes single bindings on top of the defaults below; null removes one.
+//
+//   "keymap": {
+//     "hotkeys": { "screenshot": "F9", "mute": null },
+//     "joystick": { "fire": "AltRight" },
+//     "keys": { "ArrowUp": "Q", "ArrowDown": "A", "Backslash": "SHIFT+1" }
+//   }
+//
+// Host keys use winit names ("KeyQ", "Digit1", "ArrowUp", "ShiftLeft"); "Q" and "1" are
+// accepted for letters and digits.
+
+/// Emulator functions bound to host keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hotkey {
+    Info,
+    Filter,
+    Joystick,
+    Border,
+    Fullscreen,
+    ToggleCheats,
+    VolumeDown,
+    VolumeUp,
+    Mute,
+    FullSpeed,
+    StartFresh,
+    Screenshot,
+    CheatMenu,
+    CheatFinder,
+    Rewind,
+    // Was rzx_record before recordings got their own format
+    #[serde(alias = "rzx_record")]
+    InputRecord,
+    AudioStats,
+    AyMode,
+    AvRecord,
+    AyLog,
+    Debugger,
+    Quit,
+}
+
+const DEFAULT_HOTKEYS: &[(Hotkey, KeyCode)] = &[
+    (Hotkey::Info, KeyCode::F1),
+    (Hotkey::Filter, KeyCode::F2),
+    (Hotkey::Joystick, KeyCode::F3),
+    (Hotkey::Border, KeyCode::F4),
+    (Hotkey::Fullscreen, KeyCode::F5),
+    (Hotkey::ToggleCheats, KeyCode::F6),
+    (Hotkey::VolumeDown, KeyCode::F7),
+    (Hotkey::VolumeUp, KeyCode::F8),
+    (Hotkey::Mute, KeyCode::F9),
+    (Hotkey::FullSpeed, KeyCode::F10),
+    (Hotkey::StartFresh, KeyCode::F11),
+    (Hotkey::Screenshot, KeyCode::F12),
+    (Hotkey::CheatMenu, KeyCode::Tab),
+    (Hotkey::CheatFinder, KeyCode::Backquote),
+    (Hotkey::Rewind, KeyCode::Backspace),
+    (Hotkey::InputRecord, KeyCode::Home),
+    (Hotkey::AudioStats, KeyCode::PageDown),
+    (Hotkey::AyMode, KeyCode::PageUp),
+    (Hotkey::AvRecord, KeyCode::Insert),
+    (Hotkey::AyLog, KeyCode::End),
+    (Hotkey::Debugger, KeyCode::Pause),
+    (Hotkey::Quit, KeyCode::Escape),
+];
+
+/// Joystick directions, sent to whichever joystick is selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoyDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+}
+
+impl JoyDirection {
+    pub fn kempston(self) -> KempstonKey {
+        match self {
+            Self::Up => KempstonKey::Up,
+            Self::Down => KempstonKey::Down,
+            Self::Left => KempstonKey::Left,
+            Self::Right => KempstonKey::Right,
+            Self::Fire => KempstonKey::Fire,
+        }
+    }
+
+    pub fn sinclair(self) -> SinclairKey {
+        match self {
+            Self::Up => SinclairKey::Up,
+            Self::Down => SinclairKey::Down,
+            Self::Left => SinclairKey::Left,
+            Self::Right => SinclairKey::Right,
+            Self::Fire => SinclairKey::Fire,
+        }
+    }
+
+    // Protek/AGF/Cursor: 5=L, 6=D, 7=U, 8=R, 0=fire
+    pub fn cursor(self) -> ZXKey {
+        match self {
+            Self::Up => ZXKey::N7,
+            Self::Down => ZXKey::N6,
+            Self::Left => ZXKey::N5,
+            Self::Right => ZXKey::N8,
+            Self::Fire => ZXKey::N0,
+        }
+    }
+}
+
+const DEFAULT_JOYSTICK: &[(JoyDirection, KeyCode)] = &[
+    (JoyDirection::Up, KeyCode::ArrowUp),
+    (JoyDirection::Down, KeyCode::ArrowDown),
+    (JoyDirection::Left, KeyCode::ArrowLeft),
+    (JoyDirection::Right, KeyCode::ArrowRight),
+    (JoyDirection::Fire, KeyCode::AltLeft),
+];
+
+const DEFAULT_KEYS: &[(KeyCode, ZXKey)] = &[
+    (KeyCode::KeyA, ZXKey::A), (KeyCode::KeyB, ZXKey::B), (KeyCode::KeyC, ZXKey::C), (KeyCode::KeyD, ZXKey::D),
+    (KeyCode::KeyE, ZXKey::E), (KeyCode::KeyF, ZXKey::F), (KeyCode::KeyG, ZXKey::G), (KeyCode::KeyH, ZXKey::H),
+    (KeyCode::KeyI, ZXKey::I), (KeyCode::KeyJ, ZXKey::J), (KeyCode::KeyK, ZXKey::K), (KeyCode::KeyL, ZXKey::L),
+    (KeyCode::KeyM, ZXKey::M), (KeyCode::KeyN, ZXKey::N), (KeyCode::KeyO, ZXKey::O), (KeyCode::KeyP, ZXKey::P),
+    (KeyCode::KeyQ, ZXKey::Q), (KeyCode::KeyR, ZXKey::R), (KeyCode::KeyS, ZXKey::S), (KeyCode::KeyT, ZXKey::T),
+    (KeyCode::KeyU, ZXKey::U), (KeyCode::KeyV, ZXKey::V), (KeyCode::KeyW, ZXKey::W), (KeyCode::KeyX, ZXKey::X),
+    (KeyCode::KeyY, ZXKey::Y), (KeyCode::KeyZ, ZXKey::Z),
+    (KeyCode::Digit0, ZXKey::N0), (KeyCode::Digit1, ZXKey::N1), (KeyCode::Digit2, ZXKey::N2), (KeyCode::Digit3, ZXKey::N3),
+    (KeyCode::Digit4, ZXKey::N4), (KeyCode::Digit5, ZXKey::N5), (KeyCode::Digit6, ZXKey::N6), (KeyCode::Digit7, ZXKey::N7),
+    (KeyCode::Digit8, ZXKey::N8), (KeyCode::Digit9, ZXKey::N9),
+    (KeyCode::Enter, ZXKey::Enter),
+    (KeyCode::Space, ZXKey::Space),
+    (KeyCode::ShiftLeft, ZXKey::Shift), (KeyCode::ShiftRight, ZXKey::Shift),
+    (KeyCode::ControlLeft, ZXKey::SymShift), (KeyCode::ControlRight, ZXKey::SymShift),
+];
+
+// Host keys that can be named in the keymap
+const HOST_KEYS: &[KeyCode] = &[
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+    KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+    KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+    KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight,
+    KeyCode::AltLeft, KeyCode::AltRight, KeyCode::SuperLeft, KeyCode::SuperRight,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Escape, KeyCode::CapsLock,
+    KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::Pause, KeyCode::Backquote, KeyCode::Minus, KeyCode::Equal, KeyCode::BracketLeft,
+    KeyCode::BracketRight, KeyCode::Backslash, KeyCode::Semicolon, KeyCode::Quote, KeyCode::Comma,
+    KeyCode::Period, KeyCode::Slash,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadMultiply, KeyCode::NumpadDivide,
+    KeyCode::NumpadDecimal, KeyCode::NumpadEnter,
+];
+
+/// A host key by name, checked when the config is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HostKey(KeyCode);
+
+impl TryFrom<String> for HostKey {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        let name = name.trim();
+        let full = match name.chars().next() {
+            Some(c) if name.len() == 1 && c.is_ascii_alphabetic() => format!("Key{}", c.to_ascii_uppercase()),
+            Some(c) if name.len() == 1 && c.is_ascii_digit() => format!("Digit{}", c),
+            _ => name.to_string(),
+        };
+        HOST_KEYS.iter().find(|code| format!("{:?}", code).eq_ignore_ascii_case(&full)).map(|&code| Self(code))
+            .ok_or_else(|| format!("unknown host key \"{}\"", name))
+    }
+}
+
+impl From<HostKey> for String {
+    fn from(key: HostKey) -> String {
+        format!("{:?}", key.0)
+    }
+}
+
+/// One or more ZX keys pressed together, e.g. "SHIFT+0"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ZxKeys(Vec<ZXKey>);
+
+impl TryFrom<String> for ZxKeys {
+    type Error = String;
+
+    fn try_from(names: String) -> Result<Self, String> {
+        names.split('+')
+            .map(|name| input::zx_key_from_name(name).ok_or_else(|| format!("unknown ZX key \"{}\"", name.trim())))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+impl From<ZxKeys> for String {
+    fn from(keys: ZxKeys) -> String {
+        keys.0.iter().map(|&key| input::zx_key_name(key)).collect::<Vec<_>>().join("+")
+    }
+}
+
+/// `keymap` config section: changes to the default bindings
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KeymapConfig {
+    #[serde(default)]
+    pub hotkeys: BTreeMap<Hotkey, Option<HostKey>>,
+    #[serde(default)]
+    pub joystick: BTreeMap<JoyDirection, Option<HostKey>>,
+    #[serde(default)]
+    pub keys: BTreeMap<HostKey, Option<ZxKeys>>,
+}
+
+/// Bindings in effect: the defaults with the config's changes
+pub struct Keymap {
+    hotkeys: HashMap<KeyCode, Hotkey>,
+    joystick: HashMap<KeyCode, JoyDirection>,
+    keys: HashMap<KeyCode, Vec<ZXKey>>,
+}
+
+impl Keymap {
+    pub fn new(config: &KeymapConfig) -> Self {
+        let mut hotkeys: BTreeMap<Hotkey, KeyCode> = DEFAULT_HOTKEYS.iter().copied().collect();
+        for (&hotkey, key) in &config.hotkeys {
+            match key {
+                Some(key) => hotkeys.insert(hotkey, key.0),
+                None => hotkeys.remove(&hotkey),
+            };
+        }
+        let mut joystick: BTreeMap<JoyDirection, KeyCode> = DEFAULT_JOYSTICK.iter().copied().collect();
+        for (&direction, key) in &config.joystick {
+            match key {
+                Some(key) => joystick.insert(direction, key.0),
+                None => joystick.remove(&direction),
+            };
+        }
+        let mut keys: HashMap<KeyCode, Vec<ZXKey>> = DEFAULT_KEYS.iter().map(|&(code, key)| (code, vec![key])).collect();
+        for (key, zx_keys) in &config.keys {
+            match zx_keys {
+                Some(zx_keys) => keys.insert(key.0, zx_keys.0.clone()),
+                None => keys.remove(&key.0),
+            };
+        }
+        // Of two functions on one key, the later one in `Hotkey` wins
+        Self {
+            hotkeys: hotkeys.into_iter().map(|(hotkey, code)| (code, hotkey)).collect(),
+            joystick: joystick.into_iter().map(|(direction, code)| (code, direction)).collect(),
+            keys,
+        }
+    }
+
+    pub fn hotkey(&self, code: KeyCode) -> Option<Hotkey> {
+        self.hotkeys.get(&code).copied()
+    }
+
+    /// Host key name of a hotkey, for OSD hints
+    pub fn hotkey_name(&self, hotkey: Hotkey) -> Option<String> {
+        self.hotkeys.iter().find(|(_, h)| **h == hotkey).map(|(code, _)| format!("{:?}", code).to_uppercase())
+    }
+
+    pub fn joystick(&self, code: KeyCode) -> Option<JoyDirection> {
+        self.joystick.get(&code).copied()
+    }
+
+    pub fn zx_keys(&self, code: KeyCode) -> Vec<ZXKey> {
+        self.keys.get(&code).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_key_names() {
+        let key = |name: &str| HostKey::try_from(name.to_string()).map(String::from);
+        assert_eq!(key("a"), Ok("KeyA".to_string()));
+        assert_eq!(key("5"), Ok("Digit5".to_string()));
+        assert_eq!(key(" f11 "), Ok("F11".to_string()));
+        assert_eq!(key("numpadenter"), Ok("NumpadEnter".to_string()));
+        assert!(key("F99").is_err());
+        assert!(key("").is_err());
+    }
+
+    #[test]
+    fn zx_key_combinations() {
+        let keys = |names: &str| ZxKeys::try_from(names.to_string()).map(String::from);
+        assert_eq!(keys("caps+0"), Ok("SHIFT+0".to_string()));
+        assert_eq!(keys("Return"), Ok("ENTER".to_string()));
+        assert_eq!(keys("SYM + p"), Ok("SYMSHIFT+P".to_string()));
+        assert!(keys("SHIFT+").is_err());
+        assert!(keys("CTRL").is_err());
+    }
+}
@@ -1,21 +1,25 @@
 use anyhow::{Context, Result};
 use rustzx_core::Emulator;
-use rustzx_core::host::{Snapshot, BufferCursor};
+use rustzx_core::host::{Snapshot, SnapshotRecorder, BufferCursor};
 use rustzx_core::RustzxSettings;
 use rustzx_core::zx::machine::ZXMachine;
 use rustzx_core::zx::keys::ZXKey;
+use rustzx_core::zx::sound::ay::ZXAYMode;
 use rustzx_core::zx::joy::kempston::KempstonKey;
 use rustzx_core::zx::joy::sinclair::{SinclairKey, SinclairJoyNum};
 use rustzx_core::poke::{Poke, PokeAction};
 use rustzx_core::EmulationMode;
+use rustzx_core::EmulationStopReason;
 use serde::{Serialize, Deserialize};
 use std::env;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use flate2::read::ZlibDecoder;
 use std::mem;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU16, NonZeroU32};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use winit::application::ApplicationHandler;
@@ -24,8 +28,6 @@ use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::window::{Window, WindowId, Fullscreen};
 use winit::keyboard::{KeyCode, PhysicalKey, ModifiersState};
 use winit::dpi::LogicalSize;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use ringbuf::{HeapRb, HeapProducer};
 use glow::HasContext;
 use glutin::prelude::*;
 use glutin::display::GetGlDisplay;
@@ -34,11 +36,53 @@ use glutin::surface::{Surface as GlutinSurface, WindowSurface, SurfaceAttributes
 use winit::raw_window_handle::HasWindowHandle;
 
 mod host;
-use host::AppHost;
+use host::{AppHost, BufferRecorder, DebugHit, HostHooks, RomPages};
 mod z80_loader;
 mod szx_loader;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+mod paths;
+mod session;
+mod settings;
+mod config;
+use config::LayeredConfig;
+mod persist;
+use persist::PersistRegion;
+mod rewind;
+use rewind::RewindBuffer;
+mod input;
+mod keymap;
+use keymap::{Hotkey, JoyDirection, Keymap, KeymapConfig};
+use input::InputState;
+mod recording;
+use recording::{InputPlayer, InputRecording};
+mod cli;
+mod sna;
+use sna::SnaImage;
+mod png;
+mod headless;
+mod audio;
+mod recorder;
+mod screenshot;
+mod aylog;
+use aylog::{AyLog, AyLogFormat};
+mod disasm;
+mod debugger;
+use debugger::Debugger;
+mod breakpoints;
+use breakpoints::BreakpointSet;
+mod gdb;
+use gdb::GdbServer;
+mod trace;
+use trace::{TraceOptions, Tracer};
+mod pok;
+use pok::{Trainer, ValuePrompt};
+mod cheat_menu;
+use cheat_menu::CheatMenu;
+mod cheat_finder;
+use cheat_finder::CheatFinder;
+use audio::{AudioOutput, AudioSettings};
+use recorder::AvRecorder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum BorderMode {
     Full,
     Minimal,
@@ -53,9 +97,18 @@ impl BorderMode {
             Self::None => Self::Full,
         }
     }
+
+    // Visible part of the 320x240 frame: (width, height, x offset, y offset)
+    fn visible_rect(self) -> (usize, usize, usize, usize) {
+        match self {
+            Self::Full => (320, 240, 0, 0),
+            Self::Minimal => (288, 224, 16, 8),
+            Self::None => (256, 192, 32, 24),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum FilteringMode {
     Nearest,
     Linear,
@@ -83,7 +136,7 @@ impl FilteringMode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum JoystickMode {
     Off,
     Kempston,
@@ -104,44 +157,83 @@ impl JoystickMode {
     }
 }
 
-const FOOTER_MAGIC: &[u8; 4] = b"ZXND";
-
-#[derive(Debug, Clone)]
-struct PokeEntry {
-    addr: u16,
-    value: u8,
-    original: u8,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum AyStereoMode {
+    #[serde(rename = "ABC")]
+    Abc,
+    #[serde(rename = "ACB")]
+    Acb,
+    Mono,
 }
 
-fn parse_pokes_content(content: &str) -> Vec<PokeEntry> {
-    let mut pokes = Vec::new();
-    for line in content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 5 && (parts[0] == "M" || parts[0] == "Z")
-            && let (Ok(addr), Ok(val), Ok(org)) = (
-                parts[2].parse::<u16>(),
-                parts[3].parse::<u8>(),
-                parts[4].parse::<u8>()
-            ) 
-        {
-            pokes.push(PokeEntry { addr, value: val, original: org });
+impl AyStereoMode {
+    fn next(self) -> Self {
+        match self {
+            Self::Abc => Self::Acb,
+            Self::Acb => Self::Mono,
+            Self::Mono => Self::Abc,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Abc => "ABC",
+            Self::Acb => "ACB",
+            Self::Mono => "Mono",
+        }
+    }
+
+    fn to_core(self) -> ZXAYMode {
+        match self {
+            Self::Abc => ZXAYMode::ABC,
+            Self::Acb => ZXAYMode::ACB,
+            Self::Mono => ZXAYMode::Mono,
         }
     }
-    pokes
 }
 
-fn load_pokes() -> Vec<PokeEntry> {
-    if let Ok(exe_path) = env::current_exe() {
-        let mut pok_path = exe_path.clone();
-        pok_path.set_extension("pok");
-        
-        if let Ok(content) = std::fs::read_to_string(&pok_path) {
-            return parse_pokes_content(&content);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum MachineModel {
+    #[serde(rename = "48K")]
+    Spectrum48K,
+    #[serde(rename = "128K")]
+    Spectrum128K,
+}
+
+impl MachineModel {
+    fn to_core(self) -> ZXMachine {
+        match self {
+            Self::Spectrum48K => ZXMachine::Sinclair48K,
+            Self::Spectrum128K => ZXMachine::Sinclair128K,
         }
     }
-    Vec::new()
 }
 
+/// Machine model and ROM from the config; by default the snapshot decides the model
+/// and the core's own ROMs are used
+#[derive(Clone, Default)]
+struct MachineSettings {
+    model: Option<MachineModel>,
+    // 16K for the 48K machine, 32K (128K editor page first) for the 128K one
+    rom: Option<Rc<Vec<u8>>>,
+}
+
+/// Sound options handed to the core whenever an emulator is built
+#[derive(Debug, Clone, Copy)]
+struct SoundSettings {
+    enabled: bool,
+    sample_rate: u32,
+    ay_mode: AyStereoMode,
+}
+
+impl SoundSettings {
+    fn silent() -> Self {
+        Self { enabled: false, sample_rate: 44100, ay_mode: AyStereoMode::Abc }
+    }
+}
+
+const FOOTER_MAGIC: &[u8; 4] = b"ZXND";
+
 fn load_retro_shader() -> Option<String> {
     if let Ok(exe_path) = env::current_exe() {
         let mut glsl_path = exe_path.clone();
@@ -236,6 +328,15 @@ const FONT_DATA: &[u8] = &[
     0x0, 0x0, 0x0, 0x0, 0x2, 0x0, // .
     0x2, 0x4, 0x4, 0x4, 0x2, 0x0, // (
     0x4, 0x2, 0x2, 0x2, 0x4, 0x0, // )
+    0x0, 0x2, 0x7, 0x2, 0x0, 0x0, // +
+    0x9, 0x1, 0x2, 0x4, 0x9, 0x0, // %
+    0x1, 0x1, 0x2, 0x4, 0x8, 0x0, // /
+    0x8, 0x8, 0x4, 0x2, 0x1, 0x0, // \
+    0x0, 0x0, 0x0, 0x0, 0xF, 0x0, // _
+    0x0, 0x0, 0x0, 0x2, 0x4, 0x0, // ,
+    0x2, 0x2, 0x0, 0x0, 0x0, 0x0, // '
+    0x4, 0x2, 0x1, 0x2, 0x4, 0x0, // >
+    0x0, 0x5, 0x2, 0x5, 0x0, 0x0, // *
 ];
 
 const VERTEX_SHADER_SOURCE: &str = r#"#version 330 core
@@ -270,38 +371,124 @@ struct Footer {
     shader_size: u32,
     pokes_size: u32,
     config_size: u32,
+    demo_size: u32,
+    rom_size: u32,
+}
+
+/// `cheats_enabled` / `cheats_frozen`: the names of the trainers, or true/false for all of them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum CheatSelection {
+    All(bool),
+    Trainers(Vec<String>),
+}
+
+impl CheatSelection {
+    fn includes(&self, name: &str) -> bool {
+        match self {
+            Self::All(all) => *all,
+            Self::Trainers(names) => names.iter().any(|n| n == name),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Config {
+    #[serde(default = "default_config_version")]
+    pub config_version: u32,
+    pub machine: Option<MachineModel>,
+    pub rom: Option<String>,
     #[serde(default = "default_fullscreen")]
     pub fullscreen: bool,
-    pub filtering: Option<String>,
+    pub filtering: Option<FilteringMode>,
     #[serde(default = "default_joystick")]
-    pub joystick: String,
+    pub joystick: JoystickMode,
     #[serde(default = "default_border")]
-    pub border: String,
+    pub border: BorderMode,
+    #[serde(default = "default_cheats")]
+    pub cheats_enabled: CheatSelection,
     #[serde(default = "default_cheats")]
-    pub cheats_enabled: bool,
+    pub cheats_frozen: CheatSelection,
     #[serde(default = "default_volume")]
     pub volume: u8,
+    #[serde(default = "default_resume")]
+    pub resume: bool,
+    #[serde(default = "default_rewind_seconds")]
+    pub rewind_seconds: u32,
+    #[serde(default = "default_rewind_interval")]
+    pub rewind_interval: u32,
+    #[serde(default = "default_rewind_memory")]
+    pub rewind_memory_mb: u32,
+    #[serde(default = "default_audio_latency")]
+    pub audio_latency_ms: u32,
+    pub audio_device: Option<String>,
+    pub audio_sample_rate: Option<NonZeroU32>,
+    pub audio_channels: Option<NonZeroU16>,
+    #[serde(default = "default_ay_mode")]
+    pub ay_mode: AyStereoMode,
+    #[serde(default = "default_stereo_width")]
+    pub stereo_width: u8,
+    pub screenshot_dir: Option<String>,
+    #[serde(default = "default_screenshot_scale")]
+    pub screenshot_scale: u8,
+    #[serde(default = "default_screenshot_filtered")]
+    pub screenshot_filtered: bool,
+    #[serde(default = "default_ay_log_format")]
+    pub ay_log_format: AyLogFormat,
+    #[serde(default = "default_persist_regions")]
+    pub persist_regions: Vec<PersistRegion>,
+    #[serde(default = "default_keymap")]
+    pub keymap: KeymapConfig,
 }
 
+fn default_config_version() -> u32 { config::CONFIG_VERSION }
 fn default_fullscreen() -> bool { true }
-fn default_joystick() -> String { "Off".to_string() }
-fn default_border() -> String { "Full".to_string() }
-fn default_cheats() -> bool { false }
+fn default_joystick() -> JoystickMode { JoystickMode::Off }
+fn default_border() -> BorderMode { BorderMode::Full }
+fn default_cheats() -> CheatSelection { CheatSelection::All(false) }
 fn default_volume() -> u8 { 100 }
+fn default_resume() -> bool { false }
+fn default_rewind_seconds() -> u32 { 20 }
+fn default_rewind_interval() -> u32 { 5 }
+fn default_rewind_memory() -> u32 { 32 }
+fn default_audio_latency() -> u32 { 200 } // Safe for RDP; lower it for local play
+fn default_ay_mode() -> AyStereoMode { AyStereoMode::Abc }
+fn default_stereo_width() -> u8 { 100 }
+fn default_screenshot_scale() -> u8 { 1 }
+fn default_screenshot_filtered() -> bool { false }
+fn default_ay_log_format() -> AyLogFormat { AyLogFormat::Psg }
+fn default_persist_regions() -> Vec<PersistRegion> { Vec::new() }
+fn default_keymap() -> KeymapConfig { KeymapConfig::default() }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            config_version: config::CONFIG_VERSION,
+            machine: None,
+            rom: None,
             fullscreen: true,
             filtering: None,
-            joystick: "Off".to_string(),
-            border: "Full".to_string(),
-            cheats_enabled: false,
+            joystick: JoystickMode::Off,
+            border: BorderMode::Full,
+            cheats_enabled: CheatSelection::All(false),
+            cheats_frozen: CheatSelection::All(false),
             volume: 100,
+            resume: false,
+            rewind_seconds: 20,
+            rewind_interval: 5,
+            rewind_memory_mb: 32,
+            audio_latency_ms: 200,
+            audio_device: None,
+            audio_sample_rate: None,
+            audio_channels: None,
+            ay_mode: AyStereoMode::Abc,
+            stereo_width: 100,
+            screenshot_dir: None,
+            screenshot_scale: 1,
+            screenshot_filtered: false,
+            ay_log_format: AyLogFormat::Psg,
+            persist_regions: Vec::new(),
+            keymap: KeymapConfig::default(),
         }
     }
 }
@@ -315,12 +502,13 @@ fn decompress_data(data: &[u8]) -> Result<Vec<u8>> {
 
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.contains(&"--version".to_string()) || args.contains(&"-V".to_string()) {
+    if cli::flag(&args, "--version") || cli::flag(&args, "-V") {
         println!("zexe-runner {}", env!("CARGO_PKG_VERSION"));
         return Ok(());
     }
-
-    let sound_latency = 200; // Hardcoded 200ms for RDP stability
+    if cli::flag(&args, "--list-audio-devices") {
+        return audio::list_devices();
+    }
 
     let exe_path = env::current_exe().context("Failed to get current exe path")?;
     
@@ -331,7 +519,9 @@ fn main() -> Result<()> {
     let mut snapshot_data = Vec::new();
     let mut embedded_shader = None;
     let mut embedded_pokes = None;
-    let mut embedded_config = None;
+    let mut embedded_config_json = None;
+    let mut embedded_demo = None;
+    let mut embedded_rom = None;
 
     if file_len >= footer_size as u64 {
         file.seek(SeekFrom::End(-footer_size))?;
@@ -340,337 +530,2016 @@ fn main() -> Result<()> {
         let footer: Footer = unsafe { mem::transmute(footer_buf) };
 
         if &footer.magic == FOOTER_MAGIC {
-            // Read and Decompress Snapshot
-            let snapshot_offset = file_len - (footer_size as u64) - (footer.config_size as u64) - (footer.pokes_size as u64) - (footer.shader_size as u64) - (footer.snapshot_size as u64);
-            file.seek(SeekFrom::Start(snapshot_offset))?;
-            let mut comp_snap_data = vec![0u8; footer.snapshot_size as usize];
-            file.read_exact(&mut comp_snap_data)?;
-            snapshot_data = decompress_data(&comp_snap_data).unwrap_or_default();
-
-            // Read and Decompress Shader
-            if footer.shader_size > 0 {
-                let shader_offset = file_len - (footer_size as u64) - (footer.config_size as u64) - (footer.pokes_size as u64) - (footer.shader_size as u64);
-                file.seek(SeekFrom::Start(shader_offset))?;
-                let mut comp_shader_data = vec![0u8; footer.shader_size as usize];
-                file.read_exact(&mut comp_shader_data)?;
-                if let Ok(decomp) = decompress_data(&comp_shader_data)
-                    && let Ok(s) = String::from_utf8(decomp) {
-                        embedded_shader = Some(s);
+            // Sections are stored back to back in this order right before the footer
+            let payload_size = footer.snapshot_size as u64 + footer.shader_size as u64 + footer.pokes_size as u64
+                + footer.config_size as u64 + footer.demo_size as u64 + footer.rom_size as u64;
+            let mut offset = file_len - (footer_size as u64) - payload_size;
+            let mut read_section = |size: u32| -> Result<Option<Vec<u8>>> {
+                if size == 0 {
+                    return Ok(None);
+                }
+                file.seek(SeekFrom::Start(offset))?;
+                let mut comp_data = vec![0u8; size as usize];
+                file.read_exact(&mut comp_data)?;
+                offset += size as u64;
+                Ok(decompress_data(&comp_data).ok())
+            };
+
+            // Snapshot
+            snapshot_data = read_section(footer.snapshot_size)?.unwrap_or_default();
+
+            // Shader
+            if let Some(decomp) = read_section(footer.shader_size)?
+                && let Ok(s) = String::from_utf8(decomp) {
+                    embedded_shader = Some(s);
+            }
+
+            // Pokes
+            if let Some(decomp) = read_section(footer.pokes_size)?
+                && let Ok(s) = String::from_utf8(decomp) {
+                    embedded_pokes = Some(s);
+            }
+
+            // Config
+            if let Some(decomp) = read_section(footer.config_size)? {
+                embedded_config_json = Some(decomp);
+            }
+
+            // Attract-mode demo (input recording)
+            embedded_demo = read_section(footer.demo_size)?;
+
+            // ROM image
+            embedded_rom = read_section(footer.rom_size)?;
+        }
+    }
+
+    // Defaults, embedded config, files, player settings, environment and command line
+    let layered = LayeredConfig::load(embedded_config_json, &args)?;
+    if cli::flag(&args, "--print-config") {
+        layered.print();
+        return Ok(());
+    }
+    let config = layered.config();
+    let machine = machine_settings(&config, &layered, embedded_rom)?;
+    let config_problems = layered.problems;
+
+    if cli::flag(&args, "--headless") {
+        let options = headless::HeadlessOptions::from_args(&args)?;
+        return headless::run(&snapshot_data, &machine, &options);
+    }
+
+    let audio_settings = audio_settings(&config);
+
+    // Optional input recording to play back (--play <file.zir>)
+    let play_recording = match cli::required(&args, "--play")? {
+        Some(path) => {
+            let data = std::fs::read(path).context("Failed to read input recording")?;
+            Some(InputRecording::parse(&data)?)
+        }
+        None => None,
+    };
+
+    let debug = DebugOptions::from_args(&args)?;
+
+    run_emulator(&snapshot_data, embedded_shader, embedded_pokes, config, config_problems, machine, embedded_demo, play_recording, &audio_settings, debug)
+}
+
+// An embedded ROM wins over a `rom` path from the embedded config, but not over one set
+// by a later layer. Paths from the environment and the command line are relative to the
+// working directory, those from config files to the executable.
+fn machine_settings(config: &Config, layered: &LayeredConfig, embedded_rom: Option<Vec<u8>>) -> Result<MachineSettings> {
+    let rom = match (embedded_rom, &config.rom) {
+        (Some(rom), path) if path.is_none() || !layered.overrides_embedded("rom") => Some(rom),
+        (_, Some(path)) => {
+            let path = if layered.set_by_invocation("rom") {
+                std::path::PathBuf::from(path)
+            } else {
+                let exe_path = env::current_exe().context("Failed to get current exe path")?;
+                exe_path.parent().map(|dir| dir.join(path)).unwrap_or_else(|| path.into())
+            };
+            Some(std::fs::read(&path).with_context(|| format!("Failed to read ROM {}", path.display()))?)
+        }
+        (_, None) => None,
+    };
+    Ok(MachineSettings { model: config.machine, rom: rom.map(Rc::new) })
+}
+
+/// Audio output options from the layered config, where --latency, --audio-device,
+/// --sample-rate and --channels are the command line layer
+fn audio_settings(config: &Config) -> AudioSettings {
+    AudioSettings {
+        latency_ms: config.audio_latency_ms.clamp(10, 1000),
+        device: config.audio_device.clone(),
+        sample_rate: config.audio_sample_rate.map(NonZeroU32::get),
+        channels: config.audio_channels.map(NonZeroU16::get),
+    }
+}
+
+/// Debugging options from the command line
+struct DebugOptions {
+    enabled: bool,
+    breaks: BreakpointSet,
+    addresses: Vec<u16>,
+    gdb_port: Option<u16>,
+    trace: Option<TraceOptions>,
+}
+
+impl DebugOptions {
+    fn from_args(args: &[String]) -> Result<Self> {
+        let (breaks, addresses) = BreakpointSet::from_args(args)?;
+        let gdb_port = match cli::required(args, "--gdb")? {
+            Some(port) => Some(port.parse().context("Invalid --gdb port")?),
+            None => None,
+        };
+        // Breakpoints and a GDB connection open the debugger on their own
+        let enabled = cli::flag(args, "--debug") || !breaks.is_empty() || !addresses.is_empty() || gdb_port.is_some();
+        Ok(Self { enabled, breaks, addresses, gdb_port, trace: TraceOptions::from_args(args)? })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_emulator(snapshot_data: &[u8], embedded_shader: Option<String>, embedded_pokes: Option<String>, config: Config, config_problems: Vec<String>, machine: MachineSettings, embedded_demo: Option<Vec<u8>>, play_recording: Option<InputRecording>, audio_settings: &AudioSettings, debug: DebugOptions) -> Result<()> {
+    let mut app = App::new(snapshot_data, embedded_shader, embedded_pokes, config, machine, embedded_demo, audio_settings)?;
+    // The details went to stderr while the config was loaded
+    if !config_problems.is_empty() {
+        app.set_osd_for(&format!("CONFIG: {} IGNORED - SEE CONSOLE", config_problems.join(", ")), Duration::from_secs(5));
+    }
+    app.debugger.enabled = debug.enabled;
+    app.set_breakpoints(debug.breaks, debug.addresses);
+    if let Some(port) = debug.gdb_port {
+        app.gdb = Some(GdbServer::start(port)?);
+    }
+    if debug.enabled || debug.trace.is_some() {
+        app.ensure_rom();
+        app.debugger.rom = app.hooks.debug.borrow().rom.clone();
+    }
+    if let Some(options) = debug.trace {
+        let rom = app.hooks.debug.borrow().rom.clone();
+        app.tracer = Some(Tracer::start(options, rom)?);
+    }
+    if let Some(recording) = play_recording {
+        app.start_playback(recording, false);
+    }
+
+    // Treat SIGTERM / Ctrl-C like ESC so the exit path (session saving) still runs
+    let quit_flag = app.quit_requested.clone();
+    let _ = ctrlc::set_handler(move || quit_flag.store(true, Ordering::SeqCst));
+
+    let event_loop = EventLoop::new()?;
+    event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+    event_loop.run_app(&mut app)?;
+    Ok(())
+}
+
+// Builds an emulator for the snapshot's machine. Returns it together with the
+// snapshot converted to SNA and the state shared with its debug interface.
+fn create_emulator(snapshot_data: &[u8], sound: &SoundSettings, machine_settings: &MachineSettings) -> Result<(Emulator<AppHost>, Vec<u8>, HostHooks)> {
+    let mut machine = ZXMachine::Sinclair48K;
+    let mut loaded_data = snapshot_data.to_vec();
+
+    if !snapshot_data.is_empty() {
+        if snapshot_data.len() == 49179 {
+            // Sna 48K
+        } else if snapshot_data.starts_with(b"ZXST") {
+            if let Ok((data, m)) = szx_loader::convert_szx_to_sna(snapshot_data) {
+                loaded_data = data;
+                machine = m;
+            }
+        } else if let Ok((data, m)) = z80_loader::convert_z80_to_sna(snapshot_data) {
+            loaded_data = data;
+            machine = m;
+        } else if SnaImage::is_128k_size(snapshot_data.len()) {
+            // Sna 128K (also used for saved states)
+            machine = ZXMachine::Sinclair128K;
+        }
+    }
+    if let Some(model) = machine_settings.model {
+        if !snapshot_data.is_empty() && model.to_core() != machine {
+            eprintln!("Snapshot is for the {:?}, running it on the {:?} as configured", machine, model.to_core());
+        }
+        machine = model.to_core();
+    }
+
+    let settings = RustzxSettings {
+        machine,
+        emulation_mode: EmulationMode::FrameCount(1),
+        tape_fastload_enabled: true,
+        kempston_enabled: true,
+        mouse_enabled: false,
+        load_default_rom: machine_settings.rom.is_none(),
+        sound_enabled: sound.enabled,
+        sound_sample_rate: sound.sample_rate as usize,
+        beeper_enabled: true,
+        ay_enabled: true,
+        ay_mode: sound.ay_mode.to_core(),
+        sound_volume: 100,
+    };
+
+    let mut emulator: Emulator<AppHost> = Emulator::new(settings, ())
+        .map_err(|e| anyhow::anyhow!("Failed to init emulator: {:?}", e))?;
+    if let Some(rom) = &machine_settings.rom {
+        let expected = if machine == ZXMachine::Sinclair128K { 0x8000 } else { 0x4000 };
+        if rom.len() != expected {
+            return Err(anyhow::anyhow!("The ROM is {} bytes, the {:?} needs {}", rom.len(), machine, expected));
+        }
+        emulator.load_rom(RomPages::new(rom)).map_err(|e| anyhow::anyhow!("Failed to load ROM: {:?}", e))?;
+    }
+
+    let hooks = HostHooks::default();
+    hooks.io.borrow_mut().ay_ports = machine == ZXMachine::Sinclair128K;
+    hooks.install(&mut emulator);
+
+    Ok((emulator, loaded_data, hooks))
+}
+
+/// The ROM as loaded, for the tracer: the configured image, or else the core's own ROM.
+/// The core does not expose that one, so a short program copies it into RAM on a spare
+/// emulator (128K: the editor ROM into bank 0, then the 48K BASIC ROM into bank 1).
+fn rom_image(machine_settings: &MachineSettings, is_128k: bool) -> Result<Vec<u8>> {
+    if let Some(rom) = &machine_settings.rom {
+        return Ok(rom.to_vec());
+    }
+    // DI; LD HL,0; LD DE,C000; LD BC,4000; LDIR
+    const COPY: [u8; 12] = [0xF3, 0x21, 0x00, 0x00, 0x11, 0x00, 0xC0, 0x01, 0x00, 0x40, 0xED, 0xB0];
+    // LD BC,7FFD; LD A,11; OUT (C),A (ROM 1, RAM bank 1)
+    const PAGE: [u8; 7] = [0x01, 0xFD, 0x7F, 0x3E, 0x11, 0xED, 0x79];
+    let mut program = COPY.to_vec();
+    if is_128k {
+        program.extend(PAGE);
+        program.extend(COPY);
+    }
+    program.extend([0x18, 0xFE]); // JR $
+
+    let mut image = SnaImage { header: [0; 27], pc: 0x8000, port_7ffd: is_128k.then_some(0), banks: vec![vec![0; 0x4000]; 8] };
+    image.set_reg16(23, 0x8000);
+    image.banks[2][..program.len()].copy_from_slice(&program);
+    let model = if is_128k { MachineModel::Spectrum128K } else { MachineModel::Spectrum48K };
+    let settings = MachineSettings { model: Some(model), rom: None };
+    let (mut emulator, loaded_data, _) = create_emulator(&image.to_bytes(), &SoundSettings::silent(), &settings)?;
+    load_state(&mut emulator, &loaded_data)?;
+    // Each copy takes about 5 frames
+    for _ in 0..20 {
+        emulator.emulate_frames(Duration::from_millis(20)).map_err(|e| anyhow::anyhow!("Failed to read the ROM: {:?}", e))?;
+    }
+    let image = SnaImage::parse(&save_state(&mut emulator)?)?;
+    let pages: &[usize] = if is_128k { &[0, 1] } else { &[0] };
+    Ok(pages.iter().flat_map(|&bank| image.banks[bank].iter().copied()).collect())
+}
+
+/// Saves the machine state as an SNA image
+fn save_state(emulator: &mut Emulator<AppHost>) -> Result<Vec<u8>> {
+    let mut state = Vec::new();
+    emulator.save_snapshot(SnapshotRecorder::Sna(BufferRecorder::new(&mut state)))
+        .map_err(|e| anyhow::anyhow!("Failed to save state: {:?}", e))?;
+    Ok(state)
+}
+
+fn load_state(emulator: &mut Emulator<AppHost>, state: &[u8]) -> Result<()> {
+    emulator.load_snapshot(Snapshot::Sna(BufferCursor::new(state.to_vec())))
+        .map_err(|e| anyhow::anyhow!("Failed to load state: {:?}", e))
+}
+
+struct App {
+    emulator: Emulator<AppHost>,
+    // Banked POKEs only apply to the 128K
+    is_128k: bool,
+    window: Option<Rc<Window>>,
+    
+    // OpenGL state
+    gl: Option<glow::Context>,
+    gl_surface: Option<GlutinSurface<WindowSurface>>,
+    gl_context: Option<PossiblyCurrentContext>,
+    gl_program: Option<glow::Program>,
+    gl_texture: Option<glow::Texture>,
+    gl_vao: Option<glow::VertexArray>,
+    gl_vbo: Option<glow::Buffer>,
+
+    is_fullscreen: bool,
+    border_mode: BorderMode,
+    filtering_mode: FilteringMode,
+    joystick_mode: JoystickMode,
+    keymap: Keymap,
+    trainers: Vec<Trainer>,
+    value_prompt: Option<ValuePrompt>,
+    cheat_menu: CheatMenu,
+    cheat_finder: CheatFinder,
+    osd_message: Option<String>,
+    osd_timeout: Option<Instant>,
+
+    // Session
+    initial_snapshot: Vec<u8>,
+    resume_enabled: bool,
+    // Set while asking whether to keep the resumed session, which waits until answered
+    resume_prompt: bool,
+    persist_regions: Vec<PersistRegion>,
+    quit_requested: Arc<AtomicBool>,
+
+    // Rewind
+    rewind: RewindBuffer,
+    is_rewinding: bool,
+    rewind_frames: u32,
+
+    // Audio stats
+    show_audio_stats: bool,
+    audio_stats_time: Instant,
+
+    // Input, as recorded and played back
+    input: InputState,
+    input_recording: Option<InputRecording>,
+    input_player: Option<InputPlayer>,
+    desync_reported: bool,
+
+    // Hooks and machine the emulator is rebuilt with (with `sound`)
+    hooks: HostHooks,
+    machine: MachineSettings,
+
+    // Video recording
+    av_recorder: Option<AvRecorder>,
+
+    // Screenshots
+    screenshot_dir: Option<String>,
+    screenshot_scale: u8,
+    screenshot_filtered: bool,
+    screenshot_requested: bool,
+
+    // AY log
+    ay_log: Option<AyLog>,
+    ay_log_format: AyLogFormat,
+
+    // Debugger
+    debugger: Debugger,
+    breaks: BreakpointSet,
+    gdb: Option<GdbServer>,
+    tracer: Option<Tracer>,
+
+    // Configurable Shaders
+    embedded_shader_source: Option<String>,
+    embedded_program: Option<glow::Program>,
+    retro_shader_source: Option<String>,
+    retro_program: Option<glow::Program>,
+
+    // Audio
+    audio: AudioOutput,
+    sound: SoundSettings,
+    stereo_width: u8,
+
+    modifiers: ModifiersState,
+    last_frame_time: Instant,
+    target_frame_duration: Duration,
+    is_full_speed: bool,
+    current_volume: u8,
+    is_muted: bool,
+}
+
+impl App {
+    fn new(snapshot_data: &[u8], embedded_shader: Option<String>, embedded_pokes: Option<String>, config: Config, machine: MachineSettings, embedded_demo: Option<Vec<u8>>, audio_settings: &AudioSettings) -> Result<Self> {
+        // Audio Setup (silent fallback when there is no output device)
+        let audio = AudioOutput::open(audio_settings);
+        let sound = SoundSettings {
+            enabled: true,
+            sample_rate: audio.sample_rate,
+            ay_mode: config.ay_mode,
+        };
+        let stereo_width = config.stereo_width.min(100);
+
+        let config_volume = config.volume;
+        let rewind = RewindBuffer::new(config.rewind_seconds, config.rewind_interval, config.rewind_memory_mb);
+        let (mut emulator, loaded_data, hooks) = create_emulator(snapshot_data, &sound, &machine)?;
+
+        // A saved session (if enabled and present) replaces the embedded snapshot
+        let resume_enabled = config.resume;
+        let resumed_state = if resume_enabled { session::load_session() } else { None };
+        let mut resumed = false;
+
+        if let Some(state) = resumed_state {
+            resumed = emulator.load_snapshot(Snapshot::Sna(BufferCursor::new(state))).is_ok();
+        }
+        if !resumed && !snapshot_data.is_empty() {
+            let cursor = BufferCursor::new(loaded_data.clone());
+            let snapshot = Snapshot::Sna(cursor);
+            let _ = emulator.load_snapshot(snapshot);
+        }
+
+        let retro_shader = load_retro_shader();
+        let default_filtering = if embedded_shader.is_some() {
+            FilteringMode::Embedded
+        } else if retro_shader.is_some() {
+            FilteringMode::Custom
+        } else {
+            FilteringMode::Nearest
+        };
+
+        let mut app = Self {
+            emulator,
+            is_128k: false,
+            window: None,
+            gl: None,
+            gl_surface: None,
+            gl_context: None,
+            gl_program: None,
+            gl_texture: None,
+            gl_vao: None,
+            gl_vbo: None,
+            is_fullscreen: true,
+            border_mode: BorderMode::Full,
+            filtering_mode: default_filtering,
+            joystick_mode: JoystickMode::Off,
+            keymap: Keymap::new(&config.keymap),
+            trainers: if let Some(p) = embedded_pokes {
+                pok::parse_pokes_content(&p)
+            } else {
+                pok::load_pokes()
+            },
+            value_prompt: None,
+            cheat_menu: CheatMenu::default(),
+            cheat_finder: CheatFinder::default(),
+            osd_message: None,
+            osd_timeout: None,
+            initial_snapshot: if snapshot_data.is_empty() { Vec::new() } else { loaded_data },
+            resume_enabled,
+            resume_prompt: false,
+            persist_regions: config.persist_regions.clone(),
+            quit_requested: Arc::new(AtomicBool::new(false)),
+            rewind,
+            is_rewinding: false,
+            rewind_frames: 0,
+            show_audio_stats: false,
+            audio_stats_time: Instant::now(),
+            input: InputState::default(),
+            input_recording: None,
+            input_player: None,
+            desync_reported: false,
+            hooks,
+            machine,
+            av_recorder: None,
+            screenshot_dir: config.screenshot_dir.clone(),
+            screenshot_scale: config.screenshot_scale.clamp(1, 8),
+            screenshot_filtered: config.screenshot_filtered,
+            screenshot_requested: false,
+            ay_log: None,
+            ay_log_format: config.ay_log_format,
+            debugger: Debugger::default(),
+            breaks: BreakpointSet::default(),
+            gdb: None,
+            tracer: None,
+            embedded_shader_source: embedded_shader,
+            embedded_program: None,
+            retro_shader_source: retro_shader,
+            retro_program: None,
+            audio,
+            sound,
+            stereo_width,
+            modifiers: ModifiersState::default(),
+            last_frame_time: Instant::now(),
+            target_frame_duration: Duration::from_micros(20000),
+            is_full_speed: false,
+            current_volume: config_volume,
+            is_muted: false,
+        };
+
+        // Prime the audio buffer (pre-fill with requested latency)
+        let priming_frames = (audio_settings.latency_ms / 20).max(1);
+        for _ in 0..priming_frames {
+            let _ = app.emulator.emulate_frames(app.target_frame_duration);
+            app.push_audio_samples();
+        }
+        
+        // Start audio AFTER priming
+        app.audio.play()?;
+
+        // Apply pokes if enabled on startup (trainers that ask for a value stay off)
+        for trainer in app.trainers.iter_mut() {
+            trainer.enabled = !trainer.needs_value() && config.cheats_enabled.includes(&trainer.name);
+            trainer.frozen = config.cheats_frozen.includes(&trainer.name);
+        }
+        app.is_128k = app.machine_image().is_some_and(|image| image.is_128k());
+        // A resumed session already holds the persisted regions
+        if !resumed {
+            app.restore_persisted();
+        }
+        app.apply_enabled_pokes();
+
+        if resumed {
+            app.resume_prompt = true;
+            app.show_resume_prompt();
+        } else if let Some(demo) = embedded_demo {
+            match InputRecording::parse(&demo) {
+                Ok(recording) => app.start_playback(recording, true),
+                Err(e) => eprintln!("Invalid embedded demo: {:?}", e),
+            }
+        }
+
+        // Apply dynamic config
+        app.is_fullscreen = config.fullscreen;
+        // Shader modes need their shader; without it they fall back to scanlines
+        if let Some(mode) = config.filtering {
+            app.filtering_mode = match mode {
+                FilteringMode::Embedded if app.embedded_shader_source.is_none() => FilteringMode::Scanlines,
+                FilteringMode::Custom if app.retro_shader_source.is_none() => FilteringMode::Scanlines,
+                mode => mode,
+            };
+        }
+        app.joystick_mode = config.joystick;
+        app.border_mode = config.border;
+
+        if app.audio.is_null() {
+            match app.osd_message.take() {
+                Some(msg) => app.osd_message = Some(format!("NO AUDIO - {}", msg)),
+                None => app.set_osd_for("NO AUDIO", Duration::from_secs(3)),
+            }
+        }
+
+        Ok(app)
+    }
+}
+
+impl App {
+    fn set_osd(&mut self, text: &str) {
+        self.set_osd_for(text, Duration::from_secs(2));
+    }
+
+    fn set_osd_for(&mut self, text: &str, duration: Duration) {
+        self.osd_message = Some(text.to_string());
+        self.osd_timeout = Some(Instant::now() + duration);
+    }
+
+    fn apply_enabled_pokes(&mut self) {
+        let writes: Vec<_> = self.trainers.iter().filter(|t| t.enabled).flat_map(|t| t.writes(true)).collect();
+        self.write_pokes(&writes);
+    }
+
+    // POKEs go straight to the paged-in memory. Those into a 128K bank that is not paged
+    // in go through the machine state, since the core only writes to the paged-in memory.
+    // Banks are ignored on the 48K.
+    fn write_pokes(&mut self, writes: &[(Option<u8>, u16, u8)]) {
+        if writes.is_empty() {
+            return;
+        }
+        let mut image = None;
+        if self.is_128k && writes.iter().any(|(bank, _, _)| bank.is_some()) {
+            match self.capture_state().and_then(|state| SnaImage::parse(&state)) {
+                Ok(paging) => image = Some(paging),
+                Err(e) => {
+                    eprintln!("Failed to apply POKEs: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let mut actions = Vec::new();
+        let mut unpaged = Vec::new();
+        for &(bank, addr, value) in writes {
+            match (bank, &image) {
+                (Some(bank), Some(image)) => match image.paged_address(bank, addr) {
+                    Some(addr) => actions.push(PokeAction::mem(addr, value)),
+                    None => unpaged.push((bank, addr, value)),
+                },
+                _ => actions.push(PokeAction::mem(addr, value)),
+            }
+        }
+        // The state is restored first, so that it does not undo the direct writes
+        if let Some(mut image) = image.filter(|_| !unpaged.is_empty()) {
+            for (bank, addr, value) in unpaged {
+                image.banks[bank as usize][addr as usize & 0x3FFF] = value;
+            }
+            if let Err(e) = self.restore_state(&image.to_bytes()) {
+                eprintln!("Failed to apply POKEs: {:?}", e);
+            }
+        }
+        if !actions.is_empty() {
+            self.emulator.execute_poke(ManualPoke { actions });
+        }
+    }
+
+    // Writes a trainer's POKEs (or the original values back) and records its state
+    fn set_trainer(&mut self, index: usize, enabled: bool) {
+        let writes = self.trainers[index].writes(enabled);
+        self.write_pokes(&writes);
+        self.trainers[index].enabled = enabled;
+    }
+
+    // F6: all trainers off if any is on, otherwise all on
+    fn toggle_all_trainers(&mut self) {
+        if self.trainers.is_empty() {
+            self.set_osd("NO POKES FOUND");
+            return;
+        }
+        if self.trainers.iter().any(|t| t.enabled) {
+            for i in 0..self.trainers.len() {
+                if self.trainers[i].enabled {
+                    self.set_trainer(i, false);
+                }
+            }
+            self.set_osd("POKES: OFF");
+            self.save_cheats_to_config();
+            return;
+        }
+        let mut asking = Vec::new();
+        for i in 0..self.trainers.len() {
+            if self.trainers[i].needs_value() {
+                asking.push(i);
+            } else {
+                self.set_trainer(i, true);
+            }
+        }
+        self.set_osd("POKES: ON");
+        self.save_cheats_to_config();
+        self.ask_values(asking);
+    }
+
+    fn save_cheats_to_config(&self) {
+        let names: Vec<&str> = self.trainers.iter().filter(|t| t.enabled).map(|t| t.name.as_str()).collect();
+        self.save_to_config("cheats_enabled", serde_json::Value::from(names));
+        let frozen: Vec<&str> = self.trainers.iter().filter(|t| t.frozen).map(|t| t.name.as_str()).collect();
+        self.save_to_config("cheats_frozen", serde_json::Value::from(frozen));
+    }
+
+    // --- Cheat menu ---
+    fn toggle_cheat_menu(&mut self) {
+        if self.trainers.is_empty() {
+            self.set_osd("NO POKES FOUND");
+            return;
+        }
+        self.cheat_menu.open = !self.cheat_menu.open;
+        if self.cheat_menu.open {
+            // The game waits while the menu is open
+            self.release_all_keys();
+        }
+    }
+
+    fn cheat_menu_key(&mut self, code: KeyCode) {
+        let count = self.trainers.len();
+        match code {
+            KeyCode::ArrowUp => self.cheat_menu.move_cursor(false, count),
+            KeyCode::ArrowDown => self.cheat_menu.move_cursor(true, count),
+            KeyCode::Enter | KeyCode::NumpadEnter | KeyCode::Space => {
+                let index = self.cheat_menu.selected();
+                if self.trainers[index].enabled {
+                    self.set_trainer(index, false);
+                } else if self.trainers[index].needs_value() {
+                    self.ask_values(vec![index]);
+                } else {
+                    self.set_trainer(index, true);
+                }
+                self.save_cheats_to_config();
+            }
+            // Freezing a trainer that is off also switches it on
+            KeyCode::KeyF if self.input_recording.is_some() && !self.trainers[self.cheat_menu.selected()].frozen => {
+                self.set_osd("STOP INPUT RECORDING TO FREEZE");
+            }
+            KeyCode::KeyF => {
+                let index = self.cheat_menu.selected();
+                self.trainers[index].frozen = !self.trainers[index].frozen;
+                if self.trainers[index].frozen && !self.trainers[index].enabled {
+                    if self.trainers[index].needs_value() {
+                        self.ask_values(vec![index]);
+                    } else {
+                        self.set_trainer(index, true);
+                    }
+                }
+                self.save_cheats_to_config();
+            }
+            KeyCode::Escape => self.cheat_menu.open = false,
+            _ if self.keymap.hotkey(code) == Some(Hotkey::CheatMenu) => self.cheat_menu.open = false,
+            _ => {}
+        }
+    }
+
+    // --- Cheat finder ---
+    fn toggle_cheat_finder(&mut self) {
+        self.cheat_finder.open = !self.cheat_finder.open;
+        if self.cheat_finder.open {
+            // The game waits while the finder is open
+            self.release_all_keys();
+            self.refresh_cheat_finder();
+        }
+    }
+
+    fn refresh_cheat_finder(&mut self) {
+        if let Some(image) = self.machine_image() {
+            self.cheat_finder.refresh(image);
+        }
+    }
+
+    // Frozen trainers and finder addresses are written again before every frame
+    fn apply_frozen_pokes(&mut self) {
+        let mut writes: Vec<_> = self.trainers.iter().filter(|t| t.enabled && t.frozen).flat_map(|t| t.writes(true)).collect();
+        writes.extend(self.cheat_finder.frozen().map(|p| (p.bank, p.addr, p.value)));
+        self.write_pokes(&writes);
+    }
+
+    fn cheat_finder_key(&mut self, code: KeyCode) {
+        use cheat_finder::{Entry, Filter};
+        let digit = digit_key(code);
+        let finder = &mut self.cheat_finder;
+        if let Some((entry, input)) = &mut finder.entry {
+            match code {
+                _ if digit.is_some() && input.len() < 3 => input.extend(digit),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Enter | KeyCode::NumpadEnter => {
+                    let Ok(value) = input.parse::<u8>() else { return };
+                    let entry = *entry;
+                    finder.entry = None;
+                    match entry {
+                        Entry::Search => finder.narrow(Filter::Equal(value)),
+                        Entry::Poke => {
+                            if let Some(addr) = finder.selected() {
+                                let original = finder.value_at(addr);
+                                finder.add_poke(addr, value, original, false);
+                                self.write_pokes(&[(None, addr, value)]);
+                                self.refresh_cheat_finder();
+                            }
+                        }
+                    }
+                }
+                KeyCode::Escape => finder.entry = None,
+                _ => {}
+            }
+            return;
+        }
+        match code {
+            _ if digit.is_some() => finder.entry = Some((Entry::Search, digit.into_iter().collect())),
+            KeyCode::KeyN => finder.new_search(),
+            KeyCode::Minus | KeyCode::NumpadSubtract => finder.narrow(Filter::Decreased),
+            KeyCode::Equal | KeyCode::NumpadAdd => finder.narrow(Filter::Increased),
+            KeyCode::KeyP if finder.selected().is_some() => finder.entry = Some((Entry::Poke, String::new())),
+            // Frozen values are not part of an input recording
+            KeyCode::KeyF if self.input_recording.is_some() && finder.selected().is_some_and(|addr| !finder.frozen().any(|p| p.addr == addr)) => {
+                finder.set_status("STOP INPUT RECORDING TO FREEZE");
+            }
+            KeyCode::KeyF => {
+                if let Some(addr) = finder.selected()
+                    && !finder.unfreeze(addr)
+                {
+                    let value = finder.value_at(addr);
+                    finder.add_poke(addr, value, value, true);
+                }
+            }
+            KeyCode::KeyX => {
+                if finder.pokes.is_empty() {
+                    finder.set_status("NOTHING TO EXPORT: POKE OR FREEZE FIRST");
+                    return;
+                }
+                match finder.export(paths::timestamped_file("pok")) {
+                    Ok(path) => {
+                        println!("POKEs written to {:?}", path);
+                        finder.set_status("POK SAVED");
+                    }
+                    Err(e) => {
+                        eprintln!("{:?}", e);
+                        finder.set_status("POK EXPORT FAILED");
+                    }
+                }
+            }
+            KeyCode::ArrowUp => finder.move_cursor(-1),
+            KeyCode::ArrowDown => finder.move_cursor(1),
+            KeyCode::PageUp => finder.move_cursor(-10),
+            KeyCode::PageDown => finder.move_cursor(10),
+            KeyCode::Escape => finder.open = false,
+            _ if self.keymap.hotkey(code) == Some(Hotkey::CheatFinder) => finder.open = false,
+            _ => {}
+        }
+    }
+
+    fn show_resume_prompt(&mut self) {
+        let text = match self.keymap.hotkey_name(Hotkey::StartFresh) {
+            Some(key) => format!("RESUME LAST SESSION? ENTER: RESUME  {}: START FRESH", key),
+            None => "RESUME LAST SESSION? ENTER: RESUME".to_string(),
+        };
+        self.set_osd_for(&text, Duration::from_secs(3600));
+    }
+
+    // Only the answers and Quit get through while the prompt is up
+    fn resume_prompt_key(&mut self, code: KeyCode, event_loop: &ActiveEventLoop) {
+        match code {
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                self.resume_prompt = false;
+                self.osd_timeout = Some(Instant::now());
+            }
+            _ => match self.active_hotkey(code) {
+                Some(Hotkey::StartFresh) => {
+                    self.resume_prompt = false;
+                    self.start_fresh();
+                }
+                Some(Hotkey::Quit) => self.hotkey_action(Hotkey::Quit, true, event_loop),
+                _ => {}
+            },
+        }
+    }
+
+    fn ask_values(&mut self, trainers: Vec<usize>) {
+        self.value_prompt = ValuePrompt::new(trainers);
+        if self.value_prompt.is_some() {
+            // The game waits while the value is typed in
+            self.release_all_keys();
+            self.show_value_prompt();
+        }
+    }
+
+    fn show_value_prompt(&mut self) {
+        if let Some(prompt) = &self.value_prompt {
+            let text = prompt.text(&self.trainers[prompt.trainer].name);
+            self.set_osd_for(&text, Duration::from_secs(3600));
+        }
+    }
+
+    fn value_prompt_key(&mut self, code: KeyCode) {
+        let Some(prompt) = &mut self.value_prompt else { return };
+        let digit = digit_key(code);
+        let finished = match code {
+            _ if digit.is_some() => {
+                if prompt.input.len() < 3 {
+                    prompt.input.extend(digit);
+                }
+                false
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+                false
+            }
+            KeyCode::Enter | KeyCode::NumpadEnter => match prompt.input.parse::<u8>() {
+                Ok(value) => {
+                    let index = prompt.trainer;
+                    self.trainers[index].set_value(value);
+                    self.set_trainer(index, true);
+                    self.save_cheats_to_config();
+                    true
+                }
+                Err(_) => false,
+            },
+            // The trainer stays off
+            KeyCode::Escape => true,
+            _ => false,
+        };
+        if finished && !self.value_prompt.as_mut().is_some_and(|p| p.next()) {
+            self.value_prompt = None;
+            self.osd_timeout = Some(Instant::now());
+            return;
+        }
+        self.show_value_prompt();
+    }
+
+    // Machine state helpers (SNA images)
+    fn capture_state(&mut self) -> Result<Vec<u8>> {
+        save_state(&mut self.emulator)
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+        load_state(&mut self.emulator, state)
+    }
+
+    // Runs a short program at 0x6000 with interrupts off, then puts the machine state
+    // back. Only what the state does not hold (the AY) keeps the program's effect.
+    // Returns the state as the program left it.
+    fn run_program(&mut self, program: &[u8], instructions: u32) -> Result<SnaImage> {
+        let state = self.capture_state()?;
+        let mut image = SnaImage::parse(&state)?;
+        for (i, &byte) in program.iter().enumerate() {
+            image.write(0x6000 + i as u16, byte);
+        }
+        image.pc = 0x6000;
+        image.header[19] = 0;
+        self.restore_state(&image.to_bytes())?;
+        let instruction_count = {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.replaying = true;
+            debug.resume_at = None;
+            debug.stop_after = Some(instructions);
+            debug.instruction_count
+        };
+        // The program can cross the end of a frame
+        for _ in 0..2 {
+            let stop = self.emulator.emulate_frames(self.target_frame_duration);
+            if matches!(stop, Ok(EmulationStopReason::Breakpoint)) {
+                break;
+            }
+        }
+        while self.emulator.next_audio_sample().is_some() {}
+        {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.replaying = false;
+            debug.stop_after = None;
+            debug.instruction_count = instruction_count;
+        }
+        let after = self.capture_state().and_then(|state| SnaImage::parse(&state));
+        self.restore_state(&state)?;
+        after
+    }
+
+    /// Writes the 16 AY registers, leaving register 15 selected like `dump_ay_registers`
+    fn load_ay_registers(&mut self, registers: &[u8; 16]) -> Result<()> {
+        let mut program = Vec::new();
+        for (register, &value) in registers.iter().enumerate() {
+            // LD BC,FFFD; LD A,register; OUT (C),A; LD B,BF; LD A,value; OUT (C),A
+            program.extend([0x01, 0xFD, 0xFF, 0x3E, register as u8, 0xED, 0x79, 0x06, 0xBF, 0x3E, value, 0xED, 0x79]);
+        }
+        self.run_program(&program, 16 * 6).map(|_| ())
+    }
+
+    /// Reads the 16 AY registers from the chip. Register 15 is left selected.
+    fn dump_ay_registers(&mut self) -> Result<[u8; 16]> {
+        const DUMP: [u8; 20] = [
+            0x21, 0x00, 0x61, // LD HL,6100
+            0x16, 0x00, // LD D,0
+            0x01, 0xFD, 0xFF, // loop: LD BC,FFFD
+            0xED, 0x51, // OUT (C),D
+            0xED, 0x78, // IN A,(C)
+            0x77, // LD (HL),A
+            0x23, // INC HL
+            0x14, // INC D
+            0x7A, // LD A,D
+            0xFE, 0x10, // CP 16
+            0x20, 0xF1, // JR NZ,loop
+        ];
+        let image = self.run_program(&DUMP, 2 + 16 * 9)?;
+        Ok(std::array::from_fn(|i| image.read(0x6100 + i as u16)))
+    }
+
+    fn save_session(&mut self) {
+        match self.capture_state() {
+            Ok(state) => {
+                if let Err(e) = session::save_session(&state) {
+                    eprintln!("Failed to save session: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("{:?}", e),
+        }
+    }
+
+    // High scores and progress (persist_regions) survive restarts without a session
+    fn save_persisted(&mut self) {
+        // A recording's memory is not the player's
+        if self.persist_regions.is_empty() || self.input_player.is_some() {
+            return;
+        }
+        let result = self.capture_state()
+            .and_then(|state| SnaImage::parse(&state))
+            .and_then(|image| persist::save(&image, &self.persist_regions));
+        if let Err(e) = result {
+            eprintln!("Failed to save persisted regions: {:?}", e);
+        }
+    }
+
+    fn restore_persisted(&mut self) {
+        if self.persist_regions.is_empty() {
+            return;
+        }
+        let result = self.capture_state().and_then(|state| SnaImage::parse(&state)).and_then(|mut image| {
+            if persist::restore(&mut image, &self.persist_regions)? {
+                self.restore_state(&image.to_bytes())?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to restore persisted regions: {:?}", e);
+        }
+    }
+
+    // Drop the saved session and go back to the embedded snapshot
+    fn start_fresh(&mut self) {
+        self.save_persisted();
+        session::discard_session();
+        self.rewind.clear();
+        if self.input_recording.is_some() {
+            self.save_input_recording();
+        }
+        self.input_player = None;
+        self.release_all_keys();
+        if !self.initial_snapshot.is_empty() {
+            let snapshot = mem::take(&mut self.initial_snapshot);
+            let _ = self.restore_state(&snapshot);
+            self.initial_snapshot = snapshot;
+            self.restore_persisted();
+            self.apply_enabled_pokes();
+        }
+        self.set_osd("NEW SESSION");
+    }
+
+    // Volume control helpers
+    fn set_volume(&mut self, vol: u8) {
+        self.current_volume = vol;
+    }
+
+    fn get_volume(&self) -> u8 {
+        self.current_volume
+    }
+
+    fn toggle_mute(&mut self) {
+        self.is_muted = !self.is_muted;
+        if self.is_muted {
+            self.set_osd("MUTED");
+        } else {
+            self.set_osd(&format!("VOLUME: {}", self.current_volume));
+        }
+    }
+
+    fn save_volume_to_config(&self) {
+        self.save_to_config("volume", serde_json::Value::from(self.get_volume()));
+    }
+
+    // Runtime changes go to the player's settings file, not the install directory
+    fn save_to_config(&self, key: &str, value: serde_json::Value) {
+        if let Err(e) = settings::save(key, value) {
+            eprintln!("Failed to save setting {}: {:?}", key, e);
+        }
+    }
+
+    // The AY layout is fixed when the core is built, so switching it rebuilds the
+    // emulator and carries the machine state over. The state does not hold the AY
+    // registers, so they are read from the old chip and written to the new one.
+    fn cycle_ay_mode(&mut self) {
+        let mut sound = self.sound;
+        sound.ay_mode = sound.ay_mode.next();
+        let registers = if self.hooks.io.borrow().ay_ports {
+            match self.dump_ay_registers() {
+                Ok(registers) => Some(registers),
+                Err(e) => {
+                    eprintln!("Failed to read the AY registers: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let rebuilt = self.capture_state().and_then(|state| {
+            let (mut emulator, loaded_data, _) = create_emulator(&state, &sound, &self.machine)?;
+            load_state(&mut emulator, &loaded_data)?;
+            Ok(emulator)
+        });
+        match rebuilt {
+            Ok(mut emulator) => {
+                self.hooks.install(&mut emulator);
+                self.emulator = emulator;
+                if let Some(registers) = registers
+                    && let Err(e) = self.load_ay_registers(&registers) {
+                        eprintln!("Failed to restore the AY registers: {:?}", e);
+                }
+                self.sound = sound;
+                self.set_osd(&format!("AY: {}", sound.ay_mode.name()).to_uppercase());
+                self.save_to_config("ay_mode", serde_json::json!(sound.ay_mode));
+            }
+            Err(e) => eprintln!("Failed to switch AY mode: {:?}", e),
+        }
+    }
+
+    // Advances the machine by one frame, or steps back one entry while rewinding
+    fn run_frame(&mut self) {
+        if self.debugger.paused || self.resume_prompt || self.value_prompt.is_some() || self.cheat_menu.open || self.cheat_finder.open {
+            return;
+        }
+        if self.is_rewinding {
+            self.rewind_frame();
+            return;
+        }
+
+        // Playback replaces the player's input with the recorded one
+        let mut expected_instructions = None;
+        if let Some(player) = &mut self.input_player {
+            match player.next_frame() {
+                Some(frame) => {
+                    frame.input.apply(&self.input, &mut self.emulator);
+                    self.input = frame.input;
+                    expected_instructions = Some(frame.instruction_count as u32);
+                }
+                None => {
+                    self.finish_playback();
+                    return;
+                }
+            }
+        }
+
+        self.apply_frozen_pokes();
+        let frame_input = self.input;
+        // Watchpoints, port breakpoints and the AY tap need the memory as of the frame start
+        let frame_start = if self.breaks.watchpoints.is_empty() && self.breaks.ports.is_empty() && self.ay_log.is_none() {
+            None
+        } else {
+            self.capture_state().and_then(|state| Ok((SnaImage::parse(&state)?, state))).ok()
+        };
+        {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.instruction_count = 0;
+            debug.memory = frame_start.as_ref().map(|(image, _)| image.clone());
+            if let Some(tracer) = &mut self.tracer {
+                tracer.begin_frame();
+                debug.tracing = tracer.wants_frame();
+            }
+        }
+        let mut stop_status = None;
+        let stop = loop {
+            let stop = self.emulator.emulate_frames(self.target_frame_duration);
+            if !matches!(stop, Ok(EmulationStopReason::Breakpoint)) {
+                break stop;
+            }
+            // Every stop is traced; stops made only for tracing go on right away
+            let traced_pc = self.trace_instruction();
+            if matches!(self.hooks.debug.borrow().hit, Some(DebugHit::Trace)) {
+                self.hooks.debug.borrow_mut().resume_at = traced_pc;
+                continue;
+            }
+            // Conditions and port numbers are only known once the registers are
+            match self.breakpoint_status() {
+                Some(status) => {
+                    stop_status = Some(status);
+                    break stop;
+                }
+                None => self.hooks.debug.borrow_mut().resume_at = self.emulator_pc(),
+            }
+        };
+        self.push_audio_samples();
+        self.record_av_frame();
+        if self.tracer.as_ref().is_some_and(|t| t.is_done()) {
+            self.finish_trace();
+        }
+        if let Some(log) = &mut self.ay_log {
+            let writes = std::mem::take(&mut self.hooks.io.borrow_mut().ay_writes);
+            log.push_frame(&writes);
+        }
+        let instruction_count = self.hooks.debug.borrow().instruction_count;
+
+        if let Some(recording) = &mut self.input_recording {
+            recording.record_frame(instruction_count, frame_input);
+        }
+        if let Some(expected) = expected_instructions
+            && expected != instruction_count.min(u16::MAX as u32) && !self.desync_reported {
+                self.desync_reported = true;
+                let frame = self.input_player.as_ref().map(|p| p.position()).unwrap_or(0);
+                eprintln!("Input playback desync at frame {}: expected {} instructions, got {}", frame, expected, instruction_count);
+                self.set_osd(&format!("INPUT: DESYNC AT FRAME {}", frame));
+        }
+
+        if self.rewind.is_enabled() && self.rewind.tick()
+            && let Ok(state) = self.capture_state() {
+                self.rewind.push(state);
+        }
+
+        if let Some(status) = stop_status {
+            self.debug_stop(&status);
+        } else if let Some((start, start_state)) = frame_start
+            && matches!(stop, Ok(EmulationStopReason::Completed)) && self.breaks.has_write_watches() {
+                self.check_write_watches(&start, &start_state, instruction_count);
+        }
+    }
+
+    fn rewind_frame(&mut self) {
+        if let Some(state) = self.rewind.step_back() {
+            let _ = self.restore_state(&state);
+            // Run one frame to redraw the screen; its audio is discarded (muted)
+            let _ = self.emulator.emulate_frames(self.target_frame_duration);
+            while self.emulator.next_audio_sample().is_some() {}
+            self.rewind_frames += self.rewind.interval();
+        }
+        let seconds = self.rewind_frames as f32 / 50.0;
+        self.set_osd(&format!("REWIND: -{:.1}S", seconds));
+    }
+
+    fn set_rewinding(&mut self, rewinding: bool) {
+        if !self.rewind.is_enabled() || self.input_recording.is_some() || self.input_player.is_some() {
+            if rewinding {
+                self.set_osd("REWIND: DISABLED");
+            }
+            return;
+        }
+        if rewinding && !self.is_rewinding {
+            self.rewind_frames = 0;
+        } else if !rewinding && self.is_rewinding {
+            self.osd_timeout = Some(Instant::now());
+        }
+        self.is_rewinding = rewinding;
+    }
+
+    // Input goes through these so input recording sees the full input state
+    fn send_key(&mut self, key: ZXKey, pressed: bool) {
+        self.input.set_key(key, pressed);
+        self.emulator.send_key(key, pressed);
+    }
+
+    fn send_kempston_key(&mut self, key: KempstonKey, pressed: bool) {
+        self.input.set_kempston(key, pressed);
+        self.emulator.send_kempston_key(key, pressed);
+    }
+
+    fn send_sinclair_key(&mut self, num: SinclairJoyNum, key: SinclairKey, pressed: bool) {
+        self.input.set_key(input::sinclair_to_key(num, key), pressed);
+        self.emulator.send_sinclair_key(num, key, pressed);
+    }
+
+    fn send_joystick(&mut self, direction: JoyDirection, pressed: bool) {
+        match self.joystick_mode {
+            JoystickMode::Kempston => self.send_kempston_key(direction.kempston(), pressed),
+            JoystickMode::Sinclair1 => self.send_sinclair_key(SinclairJoyNum::Fist, direction.sinclair(), pressed),
+            // Interface 2 Joy 2 is usually 1,2,3,4,5
+            JoystickMode::Sinclair2 => self.send_sinclair_key(SinclairJoyNum::Second, direction.sinclair(), pressed),
+            JoystickMode::Cursor => self.send_key(direction.cursor(), pressed),
+            JoystickMode::Off => {}
+        }
+    }
+
+    // --- Hotkeys (keymap.hotkeys) ---
+    fn active_hotkey(&self, code: KeyCode) -> Option<Hotkey> {
+        self.keymap.hotkey(code).filter(|&hotkey| hotkey != Hotkey::Debugger || self.debugger.enabled)
+    }
+
+    // Called on press, and on release for Rewind
+    fn hotkey_action(&mut self, hotkey: Hotkey, pressed: bool, event_loop: &ActiveEventLoop) {
+        match hotkey {
+            Hotkey::Info => self.set_osd(&format!("ZEXE v{}", env!("CARGO_PKG_VERSION"))),
+            Hotkey::Filter => {
+                self.filtering_mode = self.filtering_mode.next(self.embedded_program.is_some(), self.retro_program.is_some());
+                let msg = match self.filtering_mode {
+                    FilteringMode::Nearest => "FILTER: NEAREST",
+                    FilteringMode::Linear => "FILTER: LINEAR",
+                    FilteringMode::Scanlines => "FILTER: SCANLINES",
+                    FilteringMode::Embedded => "FILTER: EMBEDDED SHADER",
+                    FilteringMode::Custom => "FILTER: CUSTOM SHADER",
+                };
+                self.set_osd(msg);
+                self.save_to_config("filtering", serde_json::json!(self.filtering_mode));
+            }
+            Hotkey::Joystick => {
+                self.joystick_mode = self.joystick_mode.next();
+                let msg = match self.joystick_mode {
+                    JoystickMode::Off => "JOYSTICK: OFF",
+                    JoystickMode::Kempston => "JOYSTICK: KEMPSTON",
+                    JoystickMode::Sinclair1 => "JOYSTICK: SINCLAIR 1 (6-0)",
+                    JoystickMode::Sinclair2 => "JOYSTICK: SINCLAIR 2 (1-5)",
+                    JoystickMode::Cursor => "JOYSTICK: CURSOR (5-8)",
+                };
+                self.set_osd(msg);
+                self.save_to_config("joystick", serde_json::json!(self.joystick_mode));
+            }
+            Hotkey::Border => {
+                self.border_mode = self.border_mode.next();
+                self.set_osd(&format!("BORDER: {:?}", self.border_mode).to_uppercase());
+                self.save_to_config("border", serde_json::json!(self.border_mode));
+            }
+            Hotkey::Fullscreen => {
+                self.is_fullscreen = !self.is_fullscreen;
+                if let Some(window) = &self.window {
+                    window.set_fullscreen(self.is_fullscreen.then_some(Fullscreen::Borderless(None)));
+                }
+                self.set_osd(if self.is_fullscreen { "FULLSCREEN: ON" } else { "FULLSCREEN: OFF" });
+                self.save_to_config("fullscreen", serde_json::Value::from(self.is_fullscreen));
+            }
+            Hotkey::ToggleCheats => self.toggle_all_trainers(),
+            Hotkey::VolumeDown | Hotkey::VolumeUp => {
+                let mut vol = self.get_volume() as i16;
+                if hotkey == Hotkey::VolumeDown {
+                    vol = (vol - 10).max(0);
+                } else {
+                    vol = (vol + 10).min(200);
+                }
+                self.set_volume(vol as u8);
+                self.save_volume_to_config();
+                self.set_osd(&format!("VOLUME: {}", vol));
+            }
+            Hotkey::Mute => self.toggle_mute(),
+            Hotkey::FullSpeed => {
+                self.is_full_speed = !self.is_full_speed;
+                if self.is_full_speed {
+                    self.set_osd("SPEED: FULL");
+                } else {
+                    self.set_osd("SPEED: 1X");
+                }
+            }
+            Hotkey::StartFresh => self.start_fresh(),
+            Hotkey::Screenshot => self.take_screenshot(),
+            Hotkey::CheatMenu => self.toggle_cheat_menu(),
+            Hotkey::CheatFinder => self.toggle_cheat_finder(),
+            Hotkey::Rewind => self.set_rewinding(pressed),
+            Hotkey::InputRecord => self.toggle_input_recording(),
+            Hotkey::AudioStats => {
+                self.show_audio_stats = !self.show_audio_stats;
+                if self.show_audio_stats {
+                    self.update_audio_stats();
+                } else {
+                    self.osd_timeout = Some(Instant::now());
+                }
+            }
+            Hotkey::AyMode => self.cycle_ay_mode(),
+            Hotkey::AvRecord => self.toggle_av_recording(),
+            Hotkey::AyLog => self.toggle_ay_log(),
+            Hotkey::Debugger => self.debug_stop("PAUSED"),
+            Hotkey::Quit => event_loop.exit(),
+        }
+    }
+
+    // --- Debugger (--debug) ---
+    fn debug_stop(&mut self, status: &str) {
+        match self.capture_state().and_then(|state| SnaImage::parse(&state)) {
+            Ok(image) => {
+                self.debugger.stopped(image, status);
+                // Keys go to the panel while it is open
+                self.release_all_keys();
+                if let Some(gdb) = &mut self.gdb
+                    && gdb.awaiting_stop {
+                        gdb.awaiting_stop = false;
+                        gdb.send("S05");
+                }
+            }
+            Err(e) => eprintln!("Debugger: {:?}", e),
+        }
+    }
+
+    fn set_breakpoints(&mut self, breaks: BreakpointSet, addresses: Vec<u16>) {
+        self.hooks.debug.borrow_mut().breakpoints.extend(addresses);
+        self.breaks = breaks;
+        self.update_watches();
+    }
+
+    // The hook keeps its own copy of what it checks per instruction
+    fn update_watches(&mut self) {
+        let mut debug = self.hooks.debug.borrow_mut();
+        debug.read_watches = self.breaks.watchpoints.iter().filter(|w| w.access.reads()).copied().collect();
+        debug.port_breaks = self.breaks.ports.clone();
+    }
+
+    fn emulator_pc(&mut self) -> Option<u16> {
+        self.capture_state().and_then(|state| SnaImage::parse(&state)).ok().map(|i| i.pc)
+    }
+
+    // Why the hook stopped execution, or None when a condition or port number
+    // rules the stop out and execution should go on
+    fn breakpoint_status(&mut self) -> Option<String> {
+        let hit = self.hooks.debug.borrow().hit;
+        let image = match self.capture_state().and_then(|state| SnaImage::parse(&state)) {
+            Ok(image) => image,
+            Err(_) => return Some("BREAKPOINT".to_string()),
+        };
+        let status = self.stop_status(hit, &image);
+        // Execution goes on past the instruction without the hook seeing it again
+        if status.is_none() {
+            self.tap_ay_write(&image);
+        }
+        status
+    }
+
+    fn stop_status(&self, hit: Option<DebugHit>, image: &SnaImage) -> Option<String> {
+        let bytes = self.instruction_bytes(image);
+        match hit {
+            Some(DebugHit::Breakpoint) => {
+                self.breaks.condition_holds(image.pc, image).then(|| "BREAKPOINT".to_string())
+            }
+            // The hook decodes from frame-start memory; check again against the current
+            // one, with the registers the instruction is about to use
+            Some(DebugHit::Read(_)) => {
+                let (target, size) = breakpoints::memory_read(bytes)?.range(image)?;
+                let addr = (0..size).map(|i| target.wrapping_add(i))
+                    .find(|a| self.breaks.watchpoints.iter().any(|w| w.access.reads() && w.contains(*a)))?;
+                Some(format!("READ {:04X}", addr))
+            }
+            Some(DebugHit::Port(_)) => {
+                let (port, write) = breakpoints::port_access(bytes)?.port(image);
+                self.breaks.ports.iter().any(|p| p.matches(port, write))
+                    .then(|| format!("{} {:04X}", if write { "OUT" } else { "IN" }, port))
+            }
+            Some(DebugHit::Trace) => None,
+            _ => Some("BREAKPOINT".to_string()),
+        }
+    }
+
+    // Bytes of the instruction about to run, which may be in ROM
+    fn instruction_bytes(&self, image: &SnaImage) -> [u8; 4] {
+        let debug = self.hooks.debug.borrow();
+        [0, 1, 2, 3].map(|i| image.read_with_rom(&debug.rom, image.pc.wrapping_add(i)))
+    }
+
+    // The ROM as loaded, for decoding instructions that run there; read when first needed
+    fn ensure_rom(&mut self) {
+        if !self.hooks.debug.borrow().rom.is_empty() {
+            return;
+        }
+        match rom_image(&self.machine, self.is_128k) {
+            Ok(rom) => self.hooks.debug.borrow_mut().rom = rom,
+            Err(e) => eprintln!("Failed to read the ROM: {:?}", e),
+        }
+    }
+
+    // Passes an OUT about to run to the AY log
+    fn tap_ay_write(&mut self, image: &SnaImage) {
+        if self.ay_log.is_none() {
+            return;
+        }
+        let bytes = self.instruction_bytes(image);
+        if let Some(access) = breakpoints::port_access(bytes)
+            && let (port, true) = access.port(image) {
+                self.hooks.io.borrow_mut().record_out(port, breakpoints::out_value(bytes, image));
+        }
+    }
+
+    // Resuming lets the instruction execution stopped at run unchecked
+    fn tap_stopped_instruction(&mut self) {
+        if self.ay_log.is_some()
+            && let Some(image) = self.machine_image() {
+                self.tap_ay_write(&image);
+        }
+    }
+
+    // Logs the instruction the hook stopped at and returns its address
+    fn trace_instruction(&mut self) -> Option<u16> {
+        if !self.hooks.debug.borrow().tracing {
+            return None;
+        }
+        let tracer = self.tracer.as_mut()?;
+        let logged = save_state(&mut self.emulator)
+            .and_then(|state| SnaImage::parse(&state))
+            .and_then(|image| tracer.log(&image).map(|_| image.pc));
+        let mut debug = self.hooks.debug.borrow_mut();
+        match logged {
+            Ok(pc) => {
+                debug.tracing = !tracer.is_done();
+                Some(pc)
+            }
+            Err(e) => {
+                // Without the state the hook would stop at the same instruction forever
+                eprintln!("Trace: {:?}", e);
+                debug.tracing = false;
+                self.tracer = None;
+                None
+            }
+        }
+    }
+
+    fn finish_trace(&mut self) {
+        self.hooks.debug.borrow_mut().tracing = false;
+        if let Some(tracer) = self.tracer.take() {
+            match tracer.finish() {
+                Ok(path) => {
+                    println!("Trace written to {:?}", path);
+                    self.set_osd("TRACE SAVED");
+                }
+                Err(e) => eprintln!("Failed to save trace: {:?}", e),
+            }
+        }
+    }
+
+    // Writes are found after the fact: the frame is replayed from its start,
+    // bisecting on the instruction count until the one that changed memory is found
+    fn check_write_watches(&mut self, start: &SnaImage, start_state: &[u8], instructions: u32) {
+        let Ok(end_state) = self.capture_state() else { return };
+        let Some(changed) = SnaImage::parse(&end_state).ok().and_then(|end| self.breaks.changed_watch(start, &end)) else {
+            return;
+        };
+
+        // Smallest instruction count after which the watched memory differs
+        let (mut low, mut high) = (1, instructions);
+        let mut found = false;
+        while low <= high {
+            let mid = low + (high - low) / 2;
+            if self.replay_changes(start_state, start, mid) {
+                found = true;
+                high = mid - 1;
+            } else {
+                low = mid + 1;
+            }
+        }
+        if found && self.replay_changes(start_state, start, low) {
+            let addr = self.capture_state().and_then(|state| SnaImage::parse(&state)).ok()
+                .and_then(|now| self.breaks.changed_watch(start, &now))
+                .unwrap_or(changed);
+            self.debug_stop(&format!("WRITE {:04X}", addr));
+        } else {
+            // The replay did not reproduce the write; report it for the whole frame
+            let _ = self.restore_state(&end_state);
+            self.debug_stop(&format!("WRITE {:04X} (FRAME)", changed));
+        }
+    }
+
+    // Runs `count` instructions from the frame start and reports whether watched memory changed
+    fn replay_changes(&mut self, start_state: &[u8], start: &SnaImage, count: u32) -> bool {
+        if self.restore_state(start_state).is_err() {
+            return false;
+        }
+        {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.replaying = true;
+            debug.stop_after = Some(count);
+        }
+        let _ = self.emulator.emulate_frames(self.target_frame_duration);
+        while self.emulator.next_audio_sample().is_some() {}
+        {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.replaying = false;
+            debug.stop_after = None;
+        }
+        self.capture_state().and_then(|state| SnaImage::parse(&state)).ok()
+            .is_some_and(|now| self.breaks.changed_watch(start, &now).is_some())
+    }
+
+    // --- GDB remote protocol (--gdb PORT) ---
+    fn poll_gdb(&mut self) {
+        while let Some(event) = self.gdb.as_ref().and_then(|gdb| gdb.poll()) {
+            match event {
+                // GDB expects a stopped target when it attaches
+                gdb::Event::Connected => {
+                    if !self.debugger.paused {
+                        self.debug_stop("GDB");
+                    }
+                }
+                gdb::Event::Interrupt => {
+                    if let Some(gdb) = &mut self.gdb {
+                        gdb.awaiting_stop = false;
+                    }
+                    if !self.debugger.paused {
+                        self.debug_stop("INTERRUPTED");
+                    }
+                    self.gdb_send("S02");
+                }
+                gdb::Event::Disconnected => self.gdb_detach(),
+                gdb::Event::Packet(packet) => {
+                    if let Some(reply) = self.gdb_command(gdb::parse(&packet)) {
+                        self.gdb_send(&reply);
+                    }
+                }
+            }
+        }
+    }
+
+    fn gdb_send(&self, payload: &str) {
+        if let Some(gdb) = &self.gdb {
+            gdb.send(payload);
+        }
+    }
+
+    fn gdb_detach(&mut self) {
+        if let Some(gdb) = &mut self.gdb {
+            gdb.awaiting_stop = false;
+        }
+        if self.debugger.paused {
+            self.debug_resume();
+        }
+    }
+
+    fn machine_image(&mut self) -> Option<SnaImage> {
+        self.capture_state().and_then(|state| SnaImage::parse(&state)).ok()
+    }
+
+    // Loads a modified image back into the emulator
+    fn gdb_load_image(&mut self, image: SnaImage) -> String {
+        match self.restore_state(&image.to_bytes()) {
+            Ok(()) => {
+                self.debugger.stopped(image, "GDB");
+                "OK".to_string()
+            }
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    // Runs a command; None when the reply comes later (continue and step reply on stop)
+    fn gdb_command(&mut self, command: gdb::Command) -> Option<String> {
+        use gdb::{Breakpoint, Command};
+        let reply = match command {
+            Command::Reply(reply) => reply,
+            Command::Status => "S05".to_string(),
+            Command::ReadRegisters => self.machine_image().map(|image| gdb::registers_hex(&image)).unwrap_or_else(|| "E01".to_string()),
+            Command::ReadRegister(n) => match self.machine_image() {
+                Some(image) if n < gdb::REGISTER_COUNT => gdb::encode_hex(&gdb::register(&image, n).to_le_bytes()),
+                _ => "E01".to_string(),
+            },
+            Command::WriteRegisters(values) => match self.machine_image() {
+                Some(mut image) => {
+                    for (n, value) in values.into_iter().enumerate().take(gdb::REGISTER_COUNT) {
+                        gdb::set_register(&mut image, n, value);
+                    }
+                    self.gdb_load_image(image)
+                }
+                None => "E01".to_string(),
+            },
+            Command::WriteRegister(n, value) => match self.machine_image() {
+                Some(mut image) if n < gdb::REGISTER_COUNT => {
+                    gdb::set_register(&mut image, n, value);
+                    self.gdb_load_image(image)
+                }
+                _ => "E01".to_string(),
+            },
+            Command::ReadMemory(addr, len) => match self.machine_image() {
+                Some(image) => {
+                    let rom = &self.debugger.rom;
+                    let bytes: Vec<u8> = (0..len.min(0x800)).map(|i| image.read_with_rom(rom, addr.wrapping_add(i))).collect();
+                    gdb::encode_hex(&bytes)
+                }
+                None => "E01".to_string(),
+            },
+            // ROM is not part of the image, so writes to it are dropped
+            Command::WriteMemory(addr, bytes) => match self.machine_image() {
+                Some(mut image) => {
+                    for (i, value) in bytes.into_iter().enumerate() {
+                        image.write(addr.wrapping_add(i as u16), value);
+                    }
+                    self.gdb_load_image(image)
                 }
+                None => "E01".to_string(),
+            },
+            Command::InsertBreakpoint(Breakpoint::Execute(addr)) => {
+                let mut debug = self.hooks.debug.borrow_mut();
+                if !debug.breakpoints.contains(&addr) {
+                    debug.breakpoints.push(addr);
+                }
+                "OK".to_string()
             }
-
-            // Read and Decompress Pokes
-            if footer.pokes_size > 0 {
-                let pokes_offset = file_len - (footer_size as u64) - (footer.config_size as u64) - (footer.pokes_size as u64);
-                file.seek(SeekFrom::Start(pokes_offset))?;
-                let mut comp_pokes_data = vec![0u8; footer.pokes_size as usize];
-                file.read_exact(&mut comp_pokes_data)?;
-                if let Ok(decomp) = decompress_data(&comp_pokes_data)
-                    && let Ok(s) = String::from_utf8(decomp) {
-                        embedded_pokes = Some(s);
+            Command::RemoveBreakpoint(Breakpoint::Execute(addr)) => {
+                self.hooks.debug.borrow_mut().breakpoints.retain(|&a| a != addr);
+                "OK".to_string()
+            }
+            Command::InsertBreakpoint(Breakpoint::Watch(watch)) => {
+                self.breaks.watchpoints.push(watch);
+                self.update_watches();
+                "OK".to_string()
+            }
+            Command::RemoveBreakpoint(Breakpoint::Watch(watch)) => {
+                if let Some(i) = self.breaks.watchpoints.iter().position(|w| *w == watch) {
+                    self.breaks.watchpoints.remove(i);
                 }
+                self.update_watches();
+                "OK".to_string()
             }
-
-            // Read and Decompress Config
-            if footer.config_size > 0 {
-                let config_offset = file_len - (footer_size as u64) - (footer.config_size as u64);
-                file.seek(SeekFrom::Start(config_offset))?;
-                let mut comp_config_data = vec![0u8; footer.config_size as usize];
-                file.read_exact(&mut comp_config_data)?;
-                if let Ok(decomp) = decompress_data(&comp_config_data)
-                    && let Ok(c) = serde_json::from_slice::<Config>(&decomp) {
-                        embedded_config = Some(c);
+            Command::Continue(addr) | Command::Step(addr) if addr.is_some() => {
+                let Some(mut image) = self.machine_image() else { return Some("E01".to_string()) };
+                image.pc = addr.unwrap_or(image.pc);
+                self.gdb_load_image(image);
+                let command = if matches!(command, Command::Step(_)) { Command::Step(None) } else { Command::Continue(None) };
+                return self.gdb_command(command);
+            }
+            Command::Continue(_) => {
+                if let Some(gdb) = &mut self.gdb {
+                    gdb.awaiting_stop = true;
+                }
+                if self.debugger.paused {
+                    self.debug_resume();
                 }
+                return None;
             }
-        }
+            Command::Step(_) => {
+                if let Some(gdb) = &mut self.gdb {
+                    gdb.awaiting_stop = true;
+                }
+                self.debug_step();
+                return None;
+            }
+            // Killing would end the game; both leave it running without the debugger
+            Command::Detach => {
+                self.gdb_detach();
+                "OK".to_string()
+            }
+            Command::Kill => {
+                self.gdb_detach();
+                return None;
+            }
+        };
+        Some(reply)
     }
 
-    run_emulator(&snapshot_data, embedded_shader, embedded_pokes, embedded_config, sound_latency)
-}
-
-fn run_emulator(snapshot_data: &[u8], embedded_shader: Option<String>, embedded_pokes: Option<String>, embedded_config: Option<Config>, sound_latency: u32) -> Result<()> {
-    let mut app = App::new(snapshot_data, embedded_shader, embedded_pokes, embedded_config, sound_latency)?;
-    let event_loop = EventLoop::new()?;
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
-    event_loop.run_app(&mut app)?;
-    Ok(())
-}
-
-struct App {
-    emulator: Emulator<AppHost>,
-    window: Option<Rc<Window>>,
-    
-    // OpenGL state
-    gl: Option<glow::Context>,
-    gl_surface: Option<GlutinSurface<WindowSurface>>,
-    gl_context: Option<PossiblyCurrentContext>,
-    gl_program: Option<glow::Program>,
-    gl_texture: Option<glow::Texture>,
-    gl_vao: Option<glow::VertexArray>,
-    gl_vbo: Option<glow::Buffer>,
+    fn debug_resume(&mut self) {
+        self.tap_stopped_instruction();
+        self.hooks.debug.borrow_mut().resume_at = self.debugger.pc();
+        self.debugger.resume();
+    }
 
-    is_fullscreen: bool,
-    border_mode: BorderMode,
-    filtering_mode: FilteringMode,
-    joystick_mode: JoystickMode,
-    pokes: Vec<PokeEntry>,
-    pokes_enabled: bool,
-    osd_message: Option<String>,
-    osd_timeout: Option<Instant>,
-    
-    // Configurable Shaders
-    embedded_shader_source: Option<String>,
-    embedded_program: Option<glow::Program>,
-    retro_shader_source: Option<String>,
-    retro_program: Option<glow::Program>,
+    fn debug_step(&mut self) {
+        self.tap_stopped_instruction();
+        {
+            let mut debug = self.hooks.debug.borrow_mut();
+            debug.resume_at = self.debugger.pc();
+            debug.stop_after = Some(1);
+        }
+        // A step can cross the end of a frame, so allow a second call
+        for _ in 0..2 {
+            let stop = self.emulator.emulate_frames(self.target_frame_duration);
+            self.push_audio_samples();
+            if matches!(stop, Ok(EmulationStopReason::Breakpoint)) {
+                break;
+            }
+        }
+        self.hooks.debug.borrow_mut().stop_after = None;
+        self.debug_stop("STEP");
+    }
 
-    // Audio
-    _audio_stream: cpal::Stream,
-    audio_producer: HeapProducer<f32>,
-    audio_channels: u16,
+    fn debug_step_over(&mut self) {
+        let Some(pc) = self.debugger.pc() else { return };
+        let instruction = self.debugger.instruction_at(pc);
+        if instruction.steps_over() {
+            self.debug_run_to(pc.wrapping_add(instruction.length));
+        } else {
+            self.debug_step();
+        }
+    }
 
-    modifiers: ModifiersState,
-    last_frame_time: Instant,
-    target_frame_duration: Duration,
-    is_full_speed: bool,
-    current_volume: u8,
-    is_muted: bool,
-}
+    fn debug_run_to(&mut self, addr: u16) {
+        self.hooks.debug.borrow_mut().temp_breakpoint = Some(addr);
+        self.debug_resume();
+    }
 
-impl App {
-    fn new(snapshot_data: &[u8], embedded_shader: Option<String>, embedded_pokes: Option<String>, embedded_config: Option<Config>, sound_latency: u32) -> Result<Self> {
-        // Audio Setup
-        let audio_host = cpal::default_host();
-        let audio_device = audio_host.default_output_device().context("No audio device")?;
-        let config = audio_device.default_output_config()?;
-        let sample_rate = config.sample_rate().0;
-        let channels = config.channels();
-        
-        let rb = HeapRb::<f32>::new(sample_rate as usize * channels as usize); // 1.0s buffer for RDP
-        let (producer, mut consumer) = rb.split();
-
-        let mut last_sample = 0.0;
-        let mut stream_config: cpal::StreamConfig = config.into();
-        // Set hardware buffer size based on requested latency
-        let buffer_frames = (sample_rate as f32 * (sound_latency as f32 / 1000.0)) as u32;
-        stream_config.buffer_size = cpal::BufferSize::Fixed(buffer_frames.max(512));
-
-        let audio_stream = audio_device.build_output_stream(
-            &stream_config,
-            move |data: &mut [f32], _| {
-                for sample in data.iter_mut() {
-                    if let Some(s) = consumer.pop() {
-                        last_sample = s;
-                        *sample = s;
-                    } else {
-                        *sample = last_sample;
-                    }
-                }
-            },
-            |err| eprintln!("Audio stream error: {:?}", err),
-            None
-        )?;
-
-        let config_volume = embedded_config.as_ref().map(|c| c.volume).unwrap_or(100);
-        let mut machine = ZXMachine::Sinclair48K;
-        let mut loaded_data = snapshot_data.to_vec();
-
-        if !snapshot_data.is_empty() {
-            if snapshot_data.len() == 49179 {
-                // Sna 48K
-            } else if snapshot_data.starts_with(b"ZXST") {
-                if let Ok((data, m)) = szx_loader::convert_szx_to_sna(snapshot_data) {
-                    loaded_data = data;
-                    machine = m;
-                }
-            } else if let Ok((data, m)) = z80_loader::convert_z80_to_sna(snapshot_data) {
-                loaded_data = data;
-                machine = m;
+    fn debug_toggle_breakpoint(&mut self) {
+        let addr = self.debugger.cursor_addr();
+        let mut debug = self.hooks.debug.borrow_mut();
+        match debug.breakpoints.iter().position(|&a| a == addr) {
+            Some(i) => {
+                debug.breakpoints.remove(i);
             }
+            None => debug.breakpoints.push(addr),
         }
+    }
 
-        let settings = RustzxSettings {
-            machine,
-            emulation_mode: EmulationMode::FrameCount(1),
-            tape_fastload_enabled: true,
-            kempston_enabled: true,
-            mouse_enabled: false,
-            load_default_rom: true,
-            sound_enabled: true,
-            sound_sample_rate: sample_rate as usize,
-            beeper_enabled: true,
-            ay_enabled: true,
-            ay_mode: rustzx_core::zx::sound::ay::ZXAYMode::ABC,
-            sound_volume: 100,
-        };
+    fn debugger_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::KeyS => self.debug_step(),
+            KeyCode::KeyO => self.debug_step_over(),
+            KeyCode::KeyR => {
+                let addr = self.debugger.cursor_addr();
+                self.debug_run_to(addr);
+            }
+            KeyCode::KeyC => self.debug_resume(),
+            KeyCode::KeyB => self.debug_toggle_breakpoint(),
+            KeyCode::KeyN => self.debugger.next_bank(),
+            KeyCode::KeyM => {
+                let addr = self.debugger.cursor_addr();
+                self.debugger.show_memory_at(addr);
+            }
+            KeyCode::ArrowUp => self.debugger.move_cursor(false),
+            KeyCode::ArrowDown => self.debugger.move_cursor(true),
+            KeyCode::ArrowLeft => self.debugger.scroll_memory(-1),
+            KeyCode::ArrowRight => self.debugger.scroll_memory(1),
+            KeyCode::PageUp => self.debugger.scroll_memory(-6),
+            KeyCode::PageDown => self.debugger.scroll_memory(6),
+            _ if self.keymap.hotkey(code) == Some(Hotkey::Debugger) => self.debug_resume(),
+            _ => {}
+        }
+    }
 
-        let mut emulator: Emulator<AppHost> = Emulator::new(settings, ())
-            .map_err(|e| anyhow::anyhow!("Failed to init emulator: {:?}", e))?;
+    fn release_all_keys(&mut self) {
+        InputState::default().apply(&self.input, &mut self.emulator);
+        self.input = InputState::default();
+    }
 
-        if !snapshot_data.is_empty() {
-            let cursor = BufferCursor::new(loaded_data);
-            let snapshot = Snapshot::Sna(cursor);
-            let _ = emulator.load_snapshot(snapshot);
+    fn toggle_input_recording(&mut self) {
+        if self.input_player.is_some() {
+            self.set_osd("INPUT: PLAYBACK ACTIVE");
+            return;
+        }
+        if self.input_recording.is_some() {
+            self.save_input_recording();
+            return;
+        }
+        // Memory written every frame is not part of the recorded input, so playback would desync
+        if self.trainers.iter().any(|t| t.enabled && t.frozen) || self.cheat_finder.frozen().next().is_some() {
+            self.set_osd("INPUT: UNFREEZE CHEATS FIRST");
+            return;
+        }
+        match self.capture_state() {
+            Ok(state) => {
+                self.input_recording = Some(InputRecording::new(state));
+                // Keys already held down are part of the first recorded frame
+                self.set_osd("INPUT: RECORDING");
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                self.set_osd("INPUT: FAILED");
+            }
         }
+    }
 
-        let retro_shader = load_retro_shader();
-        let default_filtering = if embedded_shader.is_some() {
-            FilteringMode::Embedded
-        } else if retro_shader.is_some() {
-            FilteringMode::Custom
-        } else {
-            FilteringMode::Nearest
-        };
+    fn toggle_av_recording(&mut self) {
+        if self.av_recorder.is_some() {
+            self.stop_av_recording();
+            return;
+        }
+        match AvRecorder::start(self.audio.sample_rate) {
+            Ok(recorder) => {
+                self.av_recorder = Some(recorder);
+                self.set_osd("REC: AUDIO + VIDEO");
+            }
+            Err(e) => {
+                eprintln!("Failed to start recording: {:?}", e);
+                self.set_osd("REC: FAILED");
+            }
+        }
+    }
 
-        let mut app = Self {
-            emulator,
-            window: None,
-            gl: None,
-            gl_surface: None,
-            gl_context: None,
-            gl_program: None,
-            gl_texture: None,
-            gl_vao: None,
-            gl_vbo: None,
-            is_fullscreen: true,
-            border_mode: BorderMode::Full,
-            filtering_mode: default_filtering,
-            joystick_mode: JoystickMode::Off,
-            pokes: if let Some(p) = embedded_pokes {
-                parse_pokes_content(&p)
-            } else {
-                load_pokes()
-            },
-            pokes_enabled: embedded_config.as_ref().map(|c| c.cheats_enabled).unwrap_or(false),
-            osd_message: None,
-            osd_timeout: None,
-            embedded_shader_source: embedded_shader,
-            embedded_program: None,
-            retro_shader_source: retro_shader,
-            retro_program: None,
-            _audio_stream: audio_stream,
-            audio_producer: producer,
-            audio_channels: channels,
-            modifiers: ModifiersState::default(),
-            last_frame_time: Instant::now(),
-            target_frame_duration: Duration::from_micros(20000),
-            is_full_speed: false,
-            current_volume: config_volume,
-            is_muted: false,
-        };
+    fn stop_av_recording(&mut self) {
+        let Some(recorder) = self.av_recorder.take() else { return };
+        let frames = recorder.frames;
+        match recorder.finish() {
+            Ok(path) => {
+                let name = path.file_stem().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                self.set_osd(&format!("REC SAVED: {} ({:.1}S)", name.to_uppercase(), frames as f32 / 50.0));
+            }
+            Err(e) => {
+                eprintln!("Failed to finish recording: {:?}", e);
+                self.set_osd("REC: SAVE FAILED");
+            }
+        }
+    }
 
-        // Prime the audio buffer (pre-fill with requested latency)
-        let priming_frames = (sound_latency / 20).max(5);
-        for _ in 0..priming_frames {
-            let _ = app.emulator.emulate_frames(app.target_frame_duration);
-            app.push_audio_samples();
+    // Frames come from the emulated timeline, so recordings stay at 50 fps even at full speed
+    fn record_av_frame(&mut self) {
+        let Some(recorder) = &mut self.av_recorder else { return };
+        let pixels = host::compose_frame(self.emulator.screen_buffer().get_buffer(), self.emulator.border_buffer().get_buffer());
+        if let Err(e) = recorder.push_frame(&pixels) {
+            eprintln!("Recording failed: {:?}", e);
+            self.stop_av_recording();
         }
-        
-        // Start audio AFTER priming
-        app._audio_stream.play()?;
+    }
 
-        // Apply pokes if enabled on startup
-        if app.pokes_enabled && !app.pokes.is_empty() {
-            let mut actions = Vec::new();
-            for p in &app.pokes {
-                actions.push(PokeAction::mem(p.addr, p.value));
-            }
-            app.emulator.execute_poke(ManualPoke { actions });
+    // Native screenshots come straight from the emulator; filtered ones are read back
+    // from the window on the next redraw
+    fn take_screenshot(&mut self) {
+        if self.screenshot_filtered && self.gl.is_some() {
+            self.screenshot_requested = true;
+            return;
         }
+        let frame = host::compose_frame(self.emulator.screen_buffer().get_buffer(), self.emulator.border_buffer().get_buffer());
+        let (w, h, x, y) = self.border_mode.visible_rect();
+        let factor = self.screenshot_scale as usize;
+        let pixels = screenshot::scale(&screenshot::crop(&frame, 320, x, y, w, h), w, factor);
+        self.save_screenshot(&pixels, w * factor, h * factor);
+    }
 
-        // Apply dynamic config
-        if let Some(c) = embedded_config {
-            app.is_fullscreen = c.fullscreen;
-            if let Some(f_mode) = &c.filtering {
-                app.filtering_mode = match f_mode.as_str() {
-                    "Nearest" => FilteringMode::Nearest,
-                    "Linear" => FilteringMode::Linear,
-                    "Scanlines" => FilteringMode::Scanlines,
-                    "Embedded" => {
-                        if app.embedded_shader_source.is_some() {
-                            FilteringMode::Embedded
-                        } else {
-                            FilteringMode::Scanlines
-                        }
-                    },
-                    "Custom" => {
-                        if app.retro_shader_source.is_some() {
-                            FilteringMode::Custom
-                        } else {
-                            FilteringMode::Scanlines
-                        }
-                    },
-                    _ => FilteringMode::Scanlines,
+    fn save_screenshot(&mut self, pixels: &[u32], w: usize, h: usize) {
+        match screenshot::save(self.screenshot_dir.as_deref(), pixels, w, h) {
+            Ok(path) => {
+                println!("Screenshot saved to {}", path.display());
+                // Keep the end of long paths so the OSD fits on screen
+                let text = path.display().to_string();
+                let chars: Vec<char> = text.chars().collect();
+                let shown = if chars.len() > 48 {
+                    format!("...{}", chars[chars.len() - 45..].iter().collect::<String>())
+                } else {
+                    text
                 };
+                self.set_osd_for(&format!("SAVED: {}", shown), Duration::from_secs(3));
+            }
+            Err(e) => {
+                eprintln!("Failed to save screenshot: {:?}", e);
+                self.set_osd("SCREENSHOT FAILED");
             }
-            app.joystick_mode = match c.joystick.as_str() {
-                "Off" => JoystickMode::Off,
-                "Kempston" => JoystickMode::Kempston,
-                "Sinclair1" => JoystickMode::Sinclair1,
-                "Sinclair2" => JoystickMode::Sinclair2,
-                "Cursor" => JoystickMode::Cursor,
-                _ => JoystickMode::Off,
-            };
-            app.border_mode = match c.border.as_str() {
-                "Full" => BorderMode::Full,
-                "Minimal" => BorderMode::Minimal,
-                "None" => BorderMode::None,
-                _ => BorderMode::Full,
-            };
         }
+    }
 
-        Ok(app)
+    fn toggle_ay_log(&mut self) {
+        if self.ay_log.is_some() {
+            self.save_ay_log();
+            return;
+        }
+        if !self.hooks.io.borrow().ay_ports {
+            self.set_osd("AY LOG: NO AY CHIP");
+            return;
+        }
+        // The log starts from what the chip holds; OUTs are tapped from then on,
+        // including those of ROM routines
+        self.ensure_rom();
+        let registers = match self.dump_ay_registers() {
+            Ok(registers) => registers,
+            Err(e) => {
+                eprintln!("Failed to read the AY registers: {:?}", e);
+                self.set_osd("AY LOG: FAILED");
+                return;
+            }
+        };
+        {
+            let mut io = self.hooks.io.borrow_mut();
+            io.ay_register = 15;
+            io.ay_registers = registers;
+            io.ay_writes.clear();
+        }
+        self.hooks.debug.borrow_mut().ay_taps = true;
+        self.ay_log = Some(AyLog::new(self.ay_log_format, registers));
+        self.set_osd("AY LOG: RECORDING");
     }
-}
 
-impl App {
-    fn set_osd(&mut self, text: &str) {
-        self.osd_message = Some(text.to_string());
-        self.osd_timeout = Some(Instant::now() + Duration::from_secs(2));
+    fn save_ay_log(&mut self) {
+        let Some(log) = self.ay_log.take() else { return };
+        self.hooks.debug.borrow_mut().ay_taps = false;
+        let result = paths::timestamped_file(log.format().extension())
+            .context("No user data directory")
+            .and_then(|path| {
+                std::fs::write(&path, log.encode(&paths::exe_stem()))?;
+                Ok(path)
+            });
+        match result {
+            Ok(path) => {
+                let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                self.set_osd(&format!("AY LOG SAVED: {} ({:.1}S)", name.to_uppercase(), log.frame_count() as f32 / 50.0));
+            }
+            Err(e) => {
+                eprintln!("Failed to save AY log: {:?}", e);
+                self.set_osd("AY LOG: SAVE FAILED");
+            }
+        }
     }
 
-    // Volume control helpers
-    fn set_volume(&mut self, vol: u8) {
-        self.current_volume = vol;
+    fn save_input_recording(&mut self) {
+        let Some(recording) = self.input_recording.take() else { return };
+        let result = paths::timestamped_file("zir")
+            .context("No user data directory")
+            .and_then(|path| {
+                std::fs::write(&path, recording.encode()?)?;
+                Ok(path)
+            });
+        match result {
+            Ok(path) => {
+                let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                self.set_osd(&format!("INPUT SAVED: {}", name.to_uppercase()));
+            }
+            Err(e) => {
+                eprintln!("Failed to save input recording: {:?}", e);
+                self.set_osd("INPUT: SAVE FAILED");
+            }
+        }
     }
 
-    fn get_volume(&self) -> u8 {
-        self.current_volume
+    fn start_playback(&mut self, recording: InputRecording, looping: bool) {
+        self.input_recording = None;
+        self.release_all_keys();
+        if let Err(e) = self.restore_state(&recording.snapshot) {
+            eprintln!("{:?}", e);
+            self.set_osd("INPUT: FAILED");
+            return;
+        }
+        self.input_player = Some(InputPlayer::new(recording, looping));
+        self.desync_reported = false;
+        self.rewind.clear();
+        if looping {
+            self.set_osd_for("DEMO - PRESS ANY KEY", Duration::from_secs(3600));
+        } else {
+            self.set_osd("INPUT: PLAYING");
+        }
     }
 
-    fn toggle_mute(&mut self) {
-        self.is_muted = !self.is_muted;
-        if self.is_muted {
-            self.set_osd("MUTED");
+    fn finish_playback(&mut self) {
+        let looping = self.input_player.as_ref().map(|p| p.looping).unwrap_or(false);
+        if looping {
+            let snapshot = self.input_player.as_ref().map(|p| p.snapshot().to_vec()).unwrap_or_default();
+            self.release_all_keys();
+            let _ = self.restore_state(&snapshot);
+            if let Some(player) = &mut self.input_player {
+                player.restart();
+            }
         } else {
-            self.set_osd(&format!("VOLUME: {}", self.current_volume));
+            self.input_player = None;
+            self.release_all_keys();
+            self.set_osd("INPUT: PLAYBACK END");
         }
     }
 
-    fn save_volume_to_config(&self) {
-        // Try to update config.json in the parent dir
-        let config_path = std::env::current_dir().map(|mut p| { p.push("config.json"); p }).ok();
-        if let Some(path) = config_path
-            && let Ok(config) = std::fs::read_to_string(&path).and_then(|s| serde_json::from_str::<serde_json::Value>(&s).map_err(std::io::Error::other)) {
-                let mut config = config;
-                config["volume"] = serde_json::Value::from(self.get_volume());
-                let _ = std::fs::write(&path, serde_json::to_string_pretty(&config).unwrap());
+    // Any key during the attract demo hands the game over to the player
+    fn stop_demo(&mut self) {
+        self.input_player = None;
+        self.release_all_keys();
+        if !self.initial_snapshot.is_empty() {
+            let snapshot = mem::take(&mut self.initial_snapshot);
+            let _ = self.restore_state(&snapshot);
+            self.initial_snapshot = snapshot;
+            self.restore_persisted();
+            self.apply_enabled_pokes();
         }
+        self.osd_timeout = Some(Instant::now());
+    }
+
+    fn update_audio_stats(&mut self) {
+        let stats = self.audio.stats();
+        self.set_osd_for(
+            &format!(
+                "AUDIO {}MS UNDER {} OVER {} RATE {:+.2}%",
+                stats.latency_ms, stats.underruns, stats.overruns, stats.rate_adjust
+            ),
+            Duration::from_secs(1),
+        );
+        self.audio_stats_time = Instant::now();
     }
 
     fn push_audio_samples(&mut self) {
         let vol_factor = if self.is_muted { 0.0 } else { self.current_volume as f32 / 100.0 };
         while let Some(sample) = self.emulator.next_audio_sample() {
-            if self.audio_channels == 2 {
-                let _ = self.audio_producer.push(sample.left * vol_factor);
-                let _ = self.audio_producer.push(sample.right * vol_factor);
-            } else {
-                let val = (sample.left + sample.right) / 2.0 * vol_factor;
-                for _ in 0..self.audio_channels {
-                    let _ = self.audio_producer.push(val);
-                }
+            // Recordings take the core's output before any mixing or volume
+            if let Some(recorder) = &mut self.av_recorder
+                && let Err(e) = recorder.push_sample(sample.left, sample.right) {
+                    eprintln!("Recording failed: {:?}", e);
+                    self.stop_av_recording();
             }
+            // Narrow the stereo image around the centre (0% = mono, 100% = as mixed)
+            let mid = (sample.left + sample.right) / 2.0;
+            let side = (sample.left - sample.right) / 2.0 * self.stereo_width as f32 / 100.0;
+            self.audio.push_frame((mid + side) * vol_factor, (mid - side) * vol_factor);
         }
+        self.audio.end_frame();
+    }
+}
+
+// Digit typed on the main keys or the keypad
+fn digit_key(code: KeyCode) -> Option<char> {
+    match code {
+        KeyCode::Digit0 | KeyCode::Numpad0 => Some('0'),
+        KeyCode::Digit1 | KeyCode::Numpad1 => Some('1'),
+        KeyCode::Digit2 | KeyCode::Numpad2 => Some('2'),
+        KeyCode::Digit3 | KeyCode::Numpad3 => Some('3'),
+        KeyCode::Digit4 | KeyCode::Numpad4 => Some('4'),
+        KeyCode::Digit5 | KeyCode::Numpad5 => Some('5'),
+        KeyCode::Digit6 | KeyCode::Numpad6 => Some('6'),
+        KeyCode::Digit7 | KeyCode::Numpad7 => Some('7'),
+        KeyCode::Digit8 | KeyCode::Numpad8 => Some('8'),
+        KeyCode::Digit9 | KeyCode::Numpad9 => Some('9'),
+        _ => None,
     }
 }
 
@@ -681,6 +2550,20 @@ fn draw_osd_buffer(
     window_h: usize,
     scale: usize,
     padding: usize,
+) {
+    draw_text(text, buffer, window_w, window_h, padding, padding, scale, 0xFFFFFF00); // Yellow
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_text(
+    text: &str,
+    buffer: &mut [u32],
+    window_w: usize,
+    window_h: usize,
+    origin_x: usize,
+    origin_y: usize,
+    scale: usize,
+    color: u32,
 ) {
     let char_spacing = 1;
     
@@ -695,10 +2578,19 @@ fn draw_osd_buffer(
             '.' => 38 * 6,
             '(' => 39 * 6,
             ')' => 40 * 6,
+            '+' => 41 * 6,
+            '%' => 42 * 6,
+            '/' => 43 * 6,
+            '\\' => 44 * 6,
+            '_' => 45 * 6,
+            ',' => 46 * 6,
+            '\'' => 47 * 6,
+            '>' => 48 * 6,
+            '*' => 49 * 6,
             _ => continue,
         };
         
-        let char_x = padding + i * (FONT_WIDTH + char_spacing) * scale;
+        let char_x = origin_x + i * (FONT_WIDTH + char_spacing) * scale;
         
         for fy in 0..FONT_HEIGHT {
             let row = FONT_DATA[offset + fy];
@@ -707,9 +2599,9 @@ fn draw_osd_buffer(
                     for py in 0..scale {
                         for px in 0..scale {
                             let x = char_x + fx * scale + px;
-                            let y = padding + fy * scale + py;
+                            let y = origin_y + fy * scale + py;
                             if x < window_w && y < window_h {
-                                buffer[y * window_w + x] = 0xFFFFFF00; // Yellow
+                                buffer[y * window_w + x] = color;
                             }
                         }
                     }
@@ -876,6 +2768,7 @@ impl ApplicationHandler for App {
                     }
                 }
                 WindowEvent::RedrawRequested => {
+                    let mut captured = None;
                     if let (Some(gl), Some(gl_surface), Some(gl_context)) = (&self.gl, &self.gl_surface, &self.gl_context) {
                         let size = window.inner_size();
                         
@@ -883,11 +2776,7 @@ impl ApplicationHandler for App {
                         let border_buf_ptr = self.emulator.border_buffer().get_buffer();
 
                         // Source viewport
-                        let (src_w, src_h, src_x_off, src_y_off) = match self.border_mode {
-                            BorderMode::Full => (320, 240, 0, 0),
-                            BorderMode::Minimal => (288, 224, 16, 8),
-                            BorderMode::None => (256, 192, 32, 24),
-                        };
+                        let (src_w, src_h, src_x_off, src_y_off) = self.border_mode.visible_rect();
 
                         // GPU handles the mixing and alpha via Swizzle
 
@@ -956,7 +2845,7 @@ impl ApplicationHandler for App {
 
                             // 3. Optional OSD Overlay
                             if let (Some(text), Some(timeout)) = (&self.osd_message, &self.osd_timeout)
-                                && Instant::now() < *timeout {
+                                && Instant::now() < *timeout && !self.screenshot_requested && !self.debugger.paused && !self.cheat_menu.open && !self.cheat_finder.open {
                                     let char_spacing = 1;
                                     let scale = 1; 
                                     let padding = 4;
@@ -1002,6 +2891,38 @@ impl ApplicationHandler for App {
                                     );
                             }
 
+                            // 3b. Debugger panel or cheat menu over the darkened visible area
+                            let panel_lines = if self.debugger.paused {
+                                Some(self.debugger.panel_lines(&self.hooks.debug.borrow().breakpoints))
+                            } else if self.cheat_menu.open {
+                                let rows = src_h / (FONT_HEIGHT + 1) - 1;
+                                let mut lines = self.cheat_menu.lines(&self.trainers, rows);
+                                // The value prompt replaces the key help at the bottom
+                                if let (Some(prompt), Some(last)) = (&self.value_prompt, lines.last_mut()) {
+                                    last.0 = prompt.text(&self.trainers[prompt.trainer].name);
+                                }
+                                Some(lines)
+                            } else if self.cheat_finder.open {
+                                Some(self.cheat_finder.lines(src_h / (FONT_HEIGHT + 1) - 1))
+                            } else {
+                                None
+                            };
+                            if let Some(lines) = panel_lines {
+                                let frame = host::compose_frame(screen_buf, border_buf_ptr);
+                                let mut panel = screenshot::crop(&frame, 320, src_x_off, src_y_off, src_w, src_h);
+                                for pixel in panel.iter_mut() {
+                                    *pixel = ((*pixel >> 2) & 0x003F3F3F) | 0xFF000000;
+                                }
+                                for (i, (text, color)) in lines.iter().enumerate() {
+                                    draw_text(text, &mut panel, src_w, src_h, 4, 4 + i * (FONT_HEIGHT + 1), 1, *color);
+                                }
+                                let panel_u8 = std::slice::from_raw_parts(panel.as_ptr() as *const u8, panel.len() * 4);
+                                gl.tex_sub_image_2d(
+                                    glow::TEXTURE_2D, 0, 0, 0, src_w as i32, src_h as i32,
+                                    glow::BGRA, glow::UNSIGNED_BYTE, glow::PixelUnpackData::Slice(panel_u8)
+                                );
+                            }
+
                             // Common Uniforms
                             let identity: [f32; 16] = [
                                 1.0, 0.0, 0.0, 0.0,
@@ -1069,10 +2990,25 @@ impl ApplicationHandler for App {
 
                             gl.bind_vertex_array(self.gl_vao);
                             gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+
+                            // Filtered screenshot: the picture as drawn, with filter or shader
+                            if self.screenshot_requested {
+                                let (w, h) = (vis_draw_w as usize, vis_draw_h as usize);
+                                let mut rgba = vec![0u8; w * h * 4];
+                                gl.read_pixels(
+                                    v_gl_x as i32, v_gl_y as i32, w as i32, h as i32,
+                                    glow::RGBA, glow::UNSIGNED_BYTE, glow::PixelPackData::Slice(&mut rgba)
+                                );
+                                captured = Some((screenshot::from_gl_rgba(&rgba, w, h), w, h));
+                            }
                             
                             gl_surface.swap_buffers(gl_context).unwrap();
                         }
                     }
+                    if let Some((pixels, w, h)) = captured {
+                        self.screenshot_requested = false;
+                        self.save_screenshot(&pixels, w, h);
+                    }
                 },
                 WindowEvent::ModifiersChanged(new) => {
                     self.modifiers = new.state();
@@ -1080,160 +3016,44 @@ impl ApplicationHandler for App {
                 WindowEvent::KeyboardInput { event: key_event, .. } => {
                     let pressed = key_event.state == ElementState::Pressed;
                     if let PhysicalKey::Code(code) = key_event.physical_key {
-                        if pressed && (code == KeyCode::F7 || code == KeyCode::F8) {
-                            if !key_event.repeat {
-                                let mut vol = self.get_volume() as i16;
-                                if code == KeyCode::F7 {
-                                    vol = (vol - 10).max(0);
-                                } else {
-                                    vol = (vol + 10).min(200);
-                                }
-                                self.set_volume(vol as u8);
-                                self.save_volume_to_config();
-                                self.set_osd(&format!("VOLUME: {}", vol));
-                            }
-                        } else if pressed && code == KeyCode::F9 {
-                            if !key_event.repeat {
-                                self.toggle_mute();
-                            }
-                        } else if pressed && code == KeyCode::F5 {
-                            if !key_event.repeat {
-                                self.is_fullscreen = !self.is_fullscreen;
-                                if self.is_fullscreen {
-                                    window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-                                    self.set_osd("FULLSCREEN: ON");
-                                } else {
-                                    window.set_fullscreen(None);
-                                    self.set_osd("FULLSCREEN: OFF");
-                                }
+                        if self.resume_prompt {
+                            if pressed && !key_event.repeat {
+                                self.resume_prompt_key(code, event_loop);
                             }
-                        } else if pressed && code == KeyCode::F4 {
-                            if !key_event.repeat {
-                                self.border_mode = self.border_mode.next();
-                                self.set_osd(&format!("BORDER: {:?}", self.border_mode).to_uppercase());
+                        } else if self.value_prompt.is_some() {
+                            // The value prompt takes all keys while it is open
+                            if pressed {
+                                self.value_prompt_key(code);
                             }
-                        } else if pressed && code == KeyCode::F2 {
-                            if !key_event.repeat {
-                                self.filtering_mode = self.filtering_mode.next(self.embedded_program.is_some(), self.retro_program.is_some());
-                                let msg = match self.filtering_mode {
-                                    FilteringMode::Nearest => "FILTER: NEAREST",
-                                    FilteringMode::Linear => "FILTER: LINEAR",
-                                    FilteringMode::Scanlines => "FILTER: SCANLINES",
-                                    FilteringMode::Embedded => "FILTER: EMBEDDED SHADER",
-                                    FilteringMode::Custom => "FILTER: CUSTOM SHADER",
-                                };
-                                self.set_osd(msg);
+                        } else if self.cheat_menu.open {
+                            // Arrows repeat while held, toggling does not
+                            if pressed && (!key_event.repeat || matches!(code, KeyCode::ArrowUp | KeyCode::ArrowDown)) {
+                                self.cheat_menu_key(code);
                             }
-                        } else if pressed && code == KeyCode::F3 {
-                            if !key_event.repeat {
-                                self.joystick_mode = self.joystick_mode.next();
-                                let msg = match self.joystick_mode {
-                                    JoystickMode::Off => "JOYSTICK: OFF",
-                                    JoystickMode::Kempston => "JOYSTICK: KEMPSTON",
-                                    JoystickMode::Sinclair1 => "JOYSTICK: SINCLAIR 1 (6-0)",
-                                    JoystickMode::Sinclair2 => "JOYSTICK: SINCLAIR 2 (1-5)",
-                                    JoystickMode::Cursor => "JOYSTICK: CURSOR (5-8)",
-                                };
-                                self.set_osd(msg);
+                        } else if self.cheat_finder.open {
+                            if pressed {
+                                self.cheat_finder_key(code);
                             }
-                        } else if pressed && code == KeyCode::F6 {
-                            if !key_event.repeat {
-                                if self.pokes.is_empty() {
-                                    self.set_osd("NO POKES FOUND");
-                                } else {
-                                    self.pokes_enabled = !self.pokes_enabled;
-                                    let mut actions = Vec::new();
-                                    if self.pokes_enabled {
-                                        for p in &self.pokes {
-                                            actions.push(PokeAction::mem(p.addr, p.value));
-                                        }
-                                        self.set_osd("POKES: ON");
-                                    } else {
-                                        for p in &self.pokes {
-                                            actions.push(PokeAction::mem(p.addr, p.original));
-                                        }
-                                        self.set_osd("POKES: OFF");
-                                    }
-                                    let p = ManualPoke { actions };
-                                    self.emulator.execute_poke(p);
-                                }
+                        } else if self.debugger.paused {
+                            // The debugger panel takes all keys while it is open
+                            if pressed {
+                                self.debugger_key(code);
                             }
-                        } else if pressed && code == KeyCode::F1 {
-                            if !key_event.repeat {
-                                self.set_osd(&format!("ZEXE v{}", env!("CARGO_PKG_VERSION")));
+                        } else if let Some(hotkey) = self.active_hotkey(code) {
+                            // Rewind runs while its key is held
+                            if !key_event.repeat && (pressed || hotkey == Hotkey::Rewind) {
+                                self.hotkey_action(hotkey, pressed, event_loop);
                             }
-                        } else if pressed && code == KeyCode::F10 {
-                            if !key_event.repeat {
-                                self.is_full_speed = !self.is_full_speed;
-                                if self.is_full_speed {
-                                    self.set_osd("SPEED: FULL");
-                                } else {
-                                    self.set_osd("SPEED: 1X");
-                                }
+                        } else if let Some(player) = &self.input_player {
+                            // Recorded input owns the machine during playback
+                            if pressed && player.looping {
+                                self.stop_demo();
                             }
-                        } else if pressed && code == KeyCode::Escape {
-                            event_loop.exit();
+                        } else if let Some(direction) = self.keymap.joystick(code).filter(|_| self.joystick_mode != JoystickMode::Off) {
+                            self.send_joystick(direction, pressed);
                         } else {
-                            // Check for Joystick Mapping
-                            if self.joystick_mode != JoystickMode::Off {
-                                match code {
-                                    KeyCode::ArrowUp | KeyCode::ArrowDown | KeyCode::ArrowLeft | KeyCode::ArrowRight | KeyCode::AltLeft => {
-                                        match self.joystick_mode {
-                                            JoystickMode::Kempston => {
-                                                let k = match code {
-                                                    KeyCode::ArrowUp => KempstonKey::Up,
-                                                    KeyCode::ArrowDown => KempstonKey::Down,
-                                                    KeyCode::ArrowLeft => KempstonKey::Left,
-                                                    KeyCode::ArrowRight => KempstonKey::Right,
-                                                    _ => KempstonKey::Fire,
-                                                };
-                                                self.emulator.send_kempston_key(k, pressed);
-                                                return;
-                                            }
-                                            JoystickMode::Sinclair1 => {
-                                                let k = match code {
-                                                    KeyCode::ArrowUp => SinclairKey::Up,
-                                                    KeyCode::ArrowDown => SinclairKey::Down,
-                                                    KeyCode::ArrowLeft => SinclairKey::Left,
-                                                    KeyCode::ArrowRight => SinclairKey::Right,
-                                                    _ => SinclairKey::Fire,
-                                                };
-                                                self.emulator.send_sinclair_key(SinclairJoyNum::Fist, k, pressed);
-                                                return;
-                                            }
-                                            JoystickMode::Sinclair2 => {
-                                                let k = match code {
-                                                    KeyCode::ArrowUp => SinclairKey::Up,
-                                                    KeyCode::ArrowDown => SinclairKey::Down,
-                                                    KeyCode::ArrowLeft => SinclairKey::Left,
-                                                    KeyCode::ArrowRight => SinclairKey::Right,
-                                                    _ => SinclairKey::Fire,
-                                                };
-                                                // Interface 2 Joy 2 is usually 1,2,3,4,5
-                                                self.emulator.send_sinclair_key(SinclairJoyNum::Second, k, pressed);
-                                                return;
-                                            }
-                                            JoystickMode::Cursor => {
-                                                // Protek/AGF/Cursor: 5=L, 6=D, 7=U, 8=R
-                                                let k = match code {
-                                                    KeyCode::ArrowUp => ZXKey::N7,
-                                                    KeyCode::ArrowDown => ZXKey::N6,
-                                                    KeyCode::ArrowLeft => ZXKey::N5,
-                                                    KeyCode::ArrowRight => ZXKey::N8,
-                                                    _ => ZXKey::N0, // Fire is 0
-                                                };
-                                                self.emulator.send_key(k, pressed);
-                                                return;
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-
-                            if let Some(zx_key) = map_winit_key(code) {
-                                self.emulator.send_key(zx_key, pressed);
+                            for zx_key in self.keymap.zx_keys(code) {
+                                self.send_key(zx_key, pressed);
                             }
                         }
                     }
@@ -1244,16 +3064,25 @@ impl ApplicationHandler for App {
     }
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.quit_requested.load(Ordering::SeqCst) {
+            event_loop.exit();
+            return;
+        }
+        self.poll_gdb();
+
         let window = match &self.window {
             Some(w) => w.clone(),
             None => return,
         };
 
         let now = Instant::now();
+
+        if self.show_audio_stats && now.duration_since(self.audio_stats_time) >= Duration::from_millis(500) {
+            self.update_audio_stats();
+        }
         
         if self.is_full_speed {
-             let _ = self.emulator.emulate_frames(self.target_frame_duration);
-             self.push_audio_samples();
+             self.run_frame();
              self.last_frame_time = now;
              window.request_redraw();
              event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
@@ -1271,8 +3100,7 @@ impl ApplicationHandler for App {
                   }
                   
                   for _ in 0..frames_to_run {
-                      let _ = self.emulator.emulate_frames(self.target_frame_duration);
-                      self.push_audio_samples();
+                      self.run_frame();
                       self.last_frame_time += self.target_frame_duration;
                   }
 
@@ -1283,6 +3111,19 @@ impl ApplicationHandler for App {
              event_loop.set_control_flow(winit::event_loop::ControlFlow::WaitUntil(next_frame_time));
         }
     }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.stop_av_recording();
+        self.finish_trace();
+        self.save_ay_log();
+        if self.input_recording.is_some() {
+            self.save_input_recording();
+        }
+        self.save_persisted();
+        if self.resume_enabled {
+            self.save_session();
+        }
+    }
 }
 
 fn compile_retro_shader_source(gl: &glow::Context, source: &str) -> Option<glow::Program> {
@@ -1342,49 +3183,3 @@ fn compile_retro_shader_source(gl: &glow::Context, source: &str) -> Option<glow:
         }
     }
 }
-
-fn map_winit_key(code: KeyCode) -> Option<ZXKey> {
-    match code {
-        KeyCode::KeyA => Some(ZXKey::A),
-        KeyCode::KeyB => Some(ZXKey::B),
-        KeyCode::KeyC => Some(ZXKey::C),
-        KeyCode::KeyD => Some(ZXKey::D),
-        KeyCode::KeyE => Some(ZXKey::E),
-        KeyCode::KeyF => Some(ZXKey::F),
-        KeyCode::KeyG => Some(ZXKey::G),
-        KeyCode::KeyH => Some(ZXKey::H),
-        KeyCode::KeyI => Some(ZXKey::I),
-        KeyCode::KeyJ => Some(ZXKey::J),
-        KeyCode::KeyK => Some(ZXKey::K),
-        KeyCode::KeyL => Some(ZXKey::L),
-        KeyCode::KeyM => Some(ZXKey::M),
-        KeyCode::KeyN => Some(ZXKey::N),
-        KeyCode::KeyO => Some(ZXKey::O),
-        KeyCode::KeyP => Some(ZXKey::P),
-        KeyCode::KeyQ => Some(ZXKey::Q),
-        KeyCode::KeyR => Some(ZXKey::R),
-        KeyCode::KeyS => Some(ZXKey::S),
-        KeyCode::KeyT => Some(ZXKey::T),
-        KeyCode::KeyU => Some(ZXKey::U),
-        KeyCode::KeyV => Some(ZXKey::V),
-        KeyCode::KeyW => Some(ZXKey::W),
-        KeyCode::KeyX => Some(ZXKey::X),
-        KeyCode::KeyY => Some(ZXKey::Y),
-        KeyCode::KeyZ => Some(ZXKey::Z),
-        KeyCode::Digit0 => Some(ZXKey::N0),
-        KeyCode::Digit1 => Some(ZXKey::N1),
-        KeyCode::Digit2 => Some(ZXKey::N2),
-        KeyCode::Digit3 => Some(ZXKey::N3),
-        KeyCode::Digit4 => Some(ZXKey::N4),
-        KeyCode::Digit5 => Some(ZXKey::N5),
-        KeyCode::Digit6 => Some(ZXKey::N6),
-        KeyCode::Digit7 => Some(ZXKey::N7),
-        KeyCode::Digit8 => Some(ZXKey::N8),
-        KeyCode::Digit9 => Some(ZXKey::N9),
-        KeyCode::Enter => Some(ZXKey::Enter),
-        KeyCode::Space => Some(ZXKey::Space),
-        KeyCode::ShiftLeft | KeyCode::ShiftRight => Some(ZXKey::Shift),
-        KeyCode::ControlLeft | KeyCode::ControlRight => Some(ZXKey::SymShift),
-        _ => None
-    }
-}
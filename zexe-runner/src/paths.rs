@@ -0,0 +1,68 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the running executable without its extension (e.g. "my_game").
+pub fn exe_stem() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "zexe".to_string())
+}
+
+/// Per-user data directory shared by all bundles (e.g. ~/.local/share/zexe).
+pub fn data_dir() -> Option<PathBuf> {
+    let mut dir = dirs::data_dir()?;
+    dir.push("zexe");
+    Some(dir)
+}
+
+/// Per-user config directory shared by all bundles (e.g. ~/.config/zexe).
+pub fn config_dir() -> Option<PathBuf> {
+    let mut dir = dirs::config_dir()?;
+    dir.push("zexe");
+    Some(dir)
+}
+
+/// UTC timestamp for file names, e.g. "20260118-153012".
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, rem / 3600, (rem / 60) % 60, rem % 60
+    )
+}
+
+/// New file in the data directory named after the executable, e.g. "my_game-20260118-153012.zir".
+pub fn timestamped_file(extension: &str) -> Option<PathBuf> {
+    timestamped_file_in(data_dir()?, extension)
+}
+
+/// Same as `timestamped_file`, in a directory of the caller's choice.
+/// Files made within the same second get a counter, e.g. "my_game-20260118-153012-2.zir".
+pub fn timestamped_file_in(dir: PathBuf, extension: &str) -> Option<PathBuf> {
+    std::fs::create_dir_all(&dir).ok()?;
+    let base = format!("{}-{}", exe_stem(), timestamp());
+    (1..).map(|n| match n {
+        1 => dir.join(format!("{}.{}", base, extension)),
+        n => dir.join(format!("{}-{}.{}", base, n, extension)),
+    })
+    .find(|path| !path.exists())
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::paths;
+use crate::sna::SnaImage;
+
+// RAM regions kept across sessions (high-score tables, unlocked levels). They are saved
+// on exit to one file per executable, the bytes of each region one after the other, and
+// written back into memory after the snapshot is loaded.
+
+/// `persist_regions` entry: `length` bytes from `address`, in a 128K RAM `bank` if given
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "RegionFields")]
+pub struct PersistRegion {
+    pub address: u16,
+    pub length: u16,
+    pub bank: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct RegionFields {
+    address: u16,
+    length: u16,
+    bank: Option<u8>,
+}
+
+// Checked while the config is loaded, so bad regions get the usual config diagnostics
+impl TryFrom<RegionFields> for PersistRegion {
+    type Error = String;
+
+    fn try_from(fields: RegionFields) -> Result<Self, String> {
+        let RegionFields { address, length, bank } = fields;
+        let end = address as u32 + length as u32;
+        match bank {
+            Some(bank) if bank > 7 => return Err(format!("bank {} is not a 128K RAM bank (0-7)", bank)),
+            Some(bank) if (address as u32 & 0x3FFF) + length as u32 > 0x4000 => {
+                return Err(format!("region at {} ({} bytes) runs past the end of bank {}", address, length, bank));
+            }
+            _ if end > 0x10000 => return Err(format!("region at {} ({} bytes) runs past 65535", address, length)),
+            _ => {}
+        }
+        Ok(Self { address, length, bank })
+    }
+}
+
+impl PersistRegion {
+    fn addresses(&self) -> impl Iterator<Item = u16> {
+        (0..self.length).map(move |i| self.address.wrapping_add(i))
+    }
+
+    fn read(&self, image: &SnaImage, addr: u16) -> u8 {
+        match self.bank {
+            Some(bank) if image.is_128k() => image.banks[bank as usize][addr as usize & 0x3FFF],
+            _ => image.read(addr),
+        }
+    }
+
+    fn write(&self, image: &mut SnaImage, addr: u16, value: u8) {
+        match self.bank {
+            Some(bank) if image.is_128k() => image.banks[bank as usize][addr as usize & 0x3FFF] = value,
+            _ => image.write(addr, value),
+        }
+    }
+}
+
+fn persist_path() -> Option<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push(format!("{}.persist", paths::exe_stem()));
+    Some(path)
+}
+
+pub fn save(image: &SnaImage, regions: &[PersistRegion]) -> Result<()> {
+    let path = persist_path().context("No user data directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("Failed to create data directory")?;
+    }
+    let data: Vec<u8> = regions.iter().flat_map(|r| r.addresses().map(|addr| r.read(image, addr))).collect();
+    std::fs::write(&path, data).context("Failed to write persisted regions")?;
+    Ok(())
+}
+
+/// Writes the saved regions into the image; false if nothing has been saved yet
+pub fn restore(image: &mut SnaImage, regions: &[PersistRegion]) -> Result<bool> {
+    let Some(data) = persist_path().and_then(|path| std::fs::read(path).ok()) else { return Ok(false) };
+    let expected: usize = regions.iter().map(|r| r.length as usize).sum();
+    if data.len() != expected {
+        // The regions in the config changed since the file was written
+        return Err(anyhow!("Persisted regions file has {} bytes, expected {}", data.len(), expected));
+    }
+    let mut bytes = data.into_iter();
+    for region in regions {
+        for (addr, value) in region.addresses().zip(bytes.by_ref()) {
+            region.write(image, addr, value);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+use std::path::Path;
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// Encodes 0xAARRGGBB pixels (alpha ignored) as a 24-bit RGB PNG
+pub fn encode_png(pixels: &[u32], width: usize, height: usize) -> Result<Vec<u8>> {
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks_exact(width).take(height) {
+        raw.push(0); // Filter: none
+        for p in row {
+            raw.push((p >> 16) as u8);
+            raw.push((p >> 8) as u8);
+            raw.push(*p as u8);
+        }
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    let idat = encoder.finish()?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit, RGB, deflate, no filter, no interlace
+
+    let mut png = Vec::new();
+    png.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &idat);
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+pub fn save_png(path: &Path, pixels: &[u32], width: usize, height: usize) -> Result<()> {
+    std::fs::write(path, encode_png(pixels, width, height)?)?;
+    Ok(())
+}
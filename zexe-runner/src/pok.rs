@@ -0,0 +1,182 @@
+use std::env;
+
+// Trainers from .pok files, the cheat format used by most Spectrum emulators:
+//
+//   N<trainer name>
+//   M <bank> <address> <value> <original>    more POKEs of this trainer follow
+//   Z <bank> <address> <value> <original>    last POKE of this trainer
+//   Y                                        end of file
+//
+// A bank with bit 3 set (usually 8) means whatever is paged in, 0-7 is a 128K RAM
+// bank. A value of 256 means the player chooses the value when enabling the trainer.
+
+const ASK_VALUE: u16 = 256;
+
+#[derive(Debug, Clone)]
+pub struct PokeEntry {
+    pub bank: Option<u8>,
+    pub addr: u16,
+    pub value: u8,
+    // Value entered by the player (POK value 256)
+    pub ask: bool,
+    pub original: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trainer {
+    pub name: String,
+    pub pokes: Vec<PokeEntry>,
+    pub enabled: bool,
+    // Written again every frame while enabled, for games that reset lives or energy
+    pub frozen: bool,
+}
+
+impl Trainer {
+    fn new(name: &str) -> Self {
+        Self { name: name.to_string(), pokes: Vec::new(), enabled: false, frozen: false }
+    }
+
+    /// Whether the player has to enter a value to switch the trainer on
+    pub fn needs_value(&self) -> bool {
+        self.pokes.iter().any(|p| p.ask)
+    }
+
+    pub fn set_value(&mut self, value: u8) {
+        for poke in self.pokes.iter_mut().filter(|p| p.ask) {
+            poke.value = value;
+        }
+    }
+
+    /// Memory writes (bank, address, value) that switch the trainer on or off
+    pub fn writes(&self, enabled: bool) -> Vec<(Option<u8>, u16, u8)> {
+        self.pokes.iter().map(|p| (p.bank, p.addr, if enabled { p.value } else { p.original })).collect()
+    }
+}
+
+pub fn parse_pokes_content(content: &str) -> Vec<Trainer> {
+    let mut trainers: Vec<Trainer> = Vec::new();
+    // Set while the current trainer still takes POKEs (until its Z line)
+    let mut open = false;
+    for line in content.lines() {
+        let line = line.trim_end();
+        match line.chars().next() {
+            Some('N') => {
+                trainers.push(Trainer::new(line[1..].trim()));
+                open = true;
+            }
+            Some('Y') => break,
+            Some(kind @ ('M' | 'Z')) => {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 5 {
+                    continue;
+                }
+                let (Ok(bank), Ok(addr), Ok(value), Ok(original)) =
+                    (parts[1].parse::<u8>(), parts[2].parse::<u16>(), parts[3].parse::<u16>(), parts[4].parse::<u8>())
+                else {
+                    continue;
+                };
+                if value > ASK_VALUE {
+                    continue;
+                }
+                // POKEs without a name line still form a trainer
+                if !open {
+                    trainers.push(Trainer::new(&format!("Trainer {}", trainers.len() + 1)));
+                }
+                let ask = value == ASK_VALUE;
+                if let Some(trainer) = trainers.last_mut() {
+                    trainer.pokes.push(PokeEntry {
+                        bank: (bank & 0x08 == 0).then_some(bank & 0x07),
+                        addr,
+                        value: if ask { 0 } else { value as u8 },
+                        ask,
+                        original,
+                    });
+                }
+                open = kind == 'M';
+            }
+            _ => {}
+        }
+    }
+    trainers.retain(|t| !t.pokes.is_empty());
+    trainers
+}
+
+pub fn load_pokes() -> Vec<Trainer> {
+    if let Ok(exe_path) = env::current_exe() {
+        let mut pok_path = exe_path.clone();
+        pok_path.set_extension("pok");
+
+        if let Ok(content) = std::fs::read_to_string(&pok_path) {
+            return parse_pokes_content(&content);
+        }
+    }
+    Vec::new()
+}
+
+/// Value entry for trainers that use POK value 256
+pub struct ValuePrompt {
+    pub trainer: usize,
+    pub input: String,
+    // Further trainers waiting for a value
+    pub queue: Vec<usize>,
+}
+
+impl ValuePrompt {
+    pub fn new(mut trainers: Vec<usize>) -> Option<Self> {
+        if trainers.is_empty() {
+            return None;
+        }
+        let trainer = trainers.remove(0);
+        Some(Self { trainer, input: String::new(), queue: trainers })
+    }
+
+    pub fn text(&self, name: &str) -> String {
+        format!("{} - VALUE 0-255: {}_", name, self.input).to_uppercase()
+    }
+
+    /// Moves on to the next trainer; false when there is none
+    pub fn next(&mut self) -> bool {
+        if self.queue.is_empty() {
+            return false;
+        }
+        self.trainer = self.queue.remove(0);
+        self.input.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_256_asks_the_player() {
+        let trainers = parse_pokes_content("NInfinite lives\nZ 8 35136 256 53\nY\n");
+        assert_eq!(trainers.len(), 1);
+        let mut trainer = trainers[0].clone();
+        assert!(trainer.needs_value());
+        trainer.set_value(9);
+        assert_eq!(trainer.writes(true), vec![(None, 35136, 9)]);
+        assert_eq!(trainer.writes(false), vec![(None, 35136, 53)]);
+    }
+
+    #[test]
+    fn values_above_256_are_skipped() {
+        assert!(parse_pokes_content("NBad\nZ 8 35136 257 53\nY\n").is_empty());
+    }
+
+    #[test]
+    fn banks() {
+        let trainers = parse_pokes_content("NBanks\nM 8 32768 1 0\nM 3 49152 2 0\nZ 15 49153 3 0\nY\n");
+        let banks: Vec<_> = trainers[0].pokes.iter().map(|p| p.bank).collect();
+        // Bit 3 means the paged-in memory, whatever the other bits say
+        assert_eq!(banks, vec![None, Some(3), None]);
+    }
+
+    #[test]
+    fn z_closes_the_trainer() {
+        let trainers = parse_pokes_content("NOne\nM 8 30000 1 0\nZ 8 30001 2 0\nZ 8 30002 3 0\nY\nNIgnored\nZ 8 1 1 1\n");
+        let names: Vec<_> = trainers.iter().map(|t| (t.name.as_str(), t.pokes.len())).collect();
+        assert_eq!(names, vec![("One", 2), ("Trainer 2", 1)]);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Video is written at the emulated frame rate, independent of wall-clock time
+const FRAME_WIDTH: usize = 320;
+const FRAME_HEIGHT: usize = 240;
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// 16-bit stereo PCM WAV file, sizes patched in when finished
+struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path, sample_rate: u32) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let channels: u16 = 2;
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { file, data_size: 0 })
+    }
+
+    fn write_sample(&mut self, left: f32, right: f32) -> Result<()> {
+        for value in [left, right] {
+            let pcm = (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&pcm.to_le_bytes())?;
+        }
+        self.data_size += 4;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Uncompressed YUV4MPEG2 stream at 50 fps with full 4:4:4 chroma
+struct Y4mWriter {
+    file: BufWriter<File>,
+    planes: Vec<u8>,
+}
+
+impl Y4mWriter {
+    fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "YUV4MPEG2 W{} H{} F50:1 Ip A1:1 C444", FRAME_WIDTH, FRAME_HEIGHT)?;
+        Ok(Self { file, planes: vec![0; FRAME_WIDTH * FRAME_HEIGHT * 3] })
+    }
+
+    fn write_frame(&mut self, pixels: &[u32]) -> Result<()> {
+        let size = FRAME_WIDTH * FRAME_HEIGHT;
+        for (i, &argb) in pixels.iter().take(size).enumerate() {
+            let r = ((argb >> 16) & 0xFF) as i32;
+            let g = ((argb >> 8) & 0xFF) as i32;
+            let b = (argb & 0xFF) as i32;
+            // BT.601 studio range
+            self.planes[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            self.planes[size + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            self.planes[size * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.file.write_all(b"FRAME\n")?;
+        self.file.write_all(&self.planes)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Records the emulator's audio (before volume) to WAV and its frames to Y4M
+pub struct AvRecorder {
+    wav: WavWriter,
+    video: Y4mWriter,
+    path: PathBuf,
+    pub frames: u32,
+}
+
+impl AvRecorder {
+    /// Starts a recording next to the other captures, e.g. "my_game-20260118-153012.wav" + ".y4m"
+    pub fn start(sample_rate: u32) -> Result<Self> {
+        let path = crate::paths::timestamped_file("wav").context("No user data directory")?;
+        let wav = WavWriter::create(&path, sample_rate).context("Failed to create WAV file")?;
+        let video = Y4mWriter::create(&path.with_extension("y4m")).context("Failed to create video file")?;
+        Ok(Self { wav, video, path, frames: 0 })
+    }
+
+    pub fn push_sample(&mut self, left: f32, right: f32) -> Result<()> {
+        self.wav.write_sample(left, right)
+    }
+
+    /// Appends one composited 320x240 frame
+    pub fn push_frame(&mut self, pixels: &[u32]) -> Result<()> {
+        self.video.write_frame(pixels)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Closes both files and returns the path of the WAV (the video shares its name)
+    pub fn finish(self) -> Result<PathBuf> {
+        self.wav.finish()?;
+        self.video.finish()?;
+        Ok(self.path)
+    }
+}
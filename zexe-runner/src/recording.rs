@@ -0,0 +1,269 @@
+use anyhow::{Result, anyhow};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Cursor, Read, Write};
+
+use crate::input::{InputState, INPUT_STATE_SIZE};
+
+// zexe input recordings (.zir). The blocks are laid out like those of RZX 0.13 (creator,
+// SNA snapshot and one input block), but the file is not RZX and has its own signature:
+// rustzx-core gives no access to individual port reads, so instead of the raw IN values
+// each frame stores the zexe input state (keyboard matrix + Kempston byte), and the
+// counter is the number of instructions executed, not of M1 fetches. Inputs only
+// change between frames, which makes playback from the same snapshot deterministic.
+
+const SIGNATURE: &[u8; 4] = b"ZIR!";
+const CREATOR_NAME: &str = "zexe";
+const BLOCK_CREATOR: u8 = 0x10;
+const BLOCK_SNAPSHOT: u8 = 0x30;
+const BLOCK_INPUT: u8 = 0x80;
+const FLAG_COMPRESSED: u32 = 0x02;
+const REPEAT_LAST_FRAME: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordedFrame {
+    pub instruction_count: u16,
+    pub input: InputState,
+}
+
+pub struct InputRecording {
+    pub snapshot: Vec<u8>,
+    pub frames: Vec<RecordedFrame>,
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+impl InputRecording {
+    pub fn new(snapshot: Vec<u8>) -> Self {
+        Self { snapshot, frames: Vec::new() }
+    }
+
+    pub fn record_frame(&mut self, instruction_count: u32, input: InputState) {
+        self.frames.push(RecordedFrame {
+            instruction_count: instruction_count.min(u16::MAX as u32) as u16,
+            input,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(SIGNATURE);
+        out.write_u8(0)?; // Major version
+        out.write_u8(13)?; // Minor version
+        out.write_u32::<LE>(0)?; // Flags
+
+        // Creator
+        let mut name = [0u8; 20];
+        name[..CREATOR_NAME.len()].copy_from_slice(CREATOR_NAME.as_bytes());
+        out.write_u8(BLOCK_CREATOR)?;
+        out.write_u32::<LE>(29)?;
+        out.extend_from_slice(&name);
+        out.write_u16::<LE>(env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0))?;
+        out.write_u16::<LE>(env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0))?;
+
+        // Snapshot
+        let snapshot = compress(&self.snapshot)?;
+        out.write_u8(BLOCK_SNAPSHOT)?;
+        out.write_u32::<LE>(17 + snapshot.len() as u32)?;
+        out.write_u32::<LE>(FLAG_COMPRESSED)?;
+        out.extend_from_slice(b"sna\0");
+        out.write_u32::<LE>(self.snapshot.len() as u32)?;
+        out.extend_from_slice(&snapshot);
+
+        // Input recording
+        let mut frames = Vec::new();
+        let mut previous: Option<InputState> = None;
+        for frame in &self.frames {
+            frames.write_u16::<LE>(frame.instruction_count)?;
+            if previous == Some(frame.input) {
+                frames.write_u16::<LE>(REPEAT_LAST_FRAME)?;
+            } else {
+                frames.write_u16::<LE>(INPUT_STATE_SIZE as u16)?;
+                frames.extend_from_slice(&frame.input.to_bytes());
+                previous = Some(frame.input);
+            }
+        }
+        let frames = compress(&frames)?;
+        out.write_u8(BLOCK_INPUT)?;
+        out.write_u32::<LE>(18 + frames.len() as u32)?;
+        out.write_u32::<LE>(self.frames.len() as u32)?;
+        out.write_u8(0)?; // Reserved
+        out.write_u32::<LE>(0)?; // T-states at start
+        out.write_u32::<LE>(FLAG_COMPRESSED)?;
+        out.extend_from_slice(&frames);
+
+        Ok(out)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let mut signature = [0u8; 4];
+        cursor.read_exact(&mut signature)?;
+        if &signature != SIGNATURE {
+            return Err(anyhow!("Not a zexe input recording"));
+        }
+        let _major = cursor.read_u8()?;
+        let _minor = cursor.read_u8()?;
+        let flags = cursor.read_u32::<LE>()?;
+        if flags & 0x01 != 0 {
+            return Err(anyhow!("Signed recordings are not supported"));
+        }
+
+        let mut snapshot = None;
+        let mut frames = Vec::new();
+
+        let total_len = data.len() as u64;
+        while cursor.position() < total_len {
+            let block_start = cursor.position();
+            let id = cursor.read_u8()?;
+            let size = cursor.read_u32::<LE>()? as u64;
+            if size < 5 || size > total_len - block_start {
+                return Err(anyhow!("Corrupt recording block"));
+            }
+            // Bytes after the block's fixed fields
+            let body_len = |fixed: u64| size.checked_sub(fixed).map(|n| n as usize).ok_or_else(|| anyhow!("Corrupt recording block"));
+
+            match id {
+                BLOCK_SNAPSHOT => {
+                    let flags = cursor.read_u32::<LE>()?;
+                    let mut ext = [0u8; 4];
+                    cursor.read_exact(&mut ext)?;
+                    let _len = cursor.read_u32::<LE>()?;
+                    if flags & 0x01 != 0 || &ext[..3] != b"sna" {
+                        return Err(anyhow!("Unsupported recording snapshot"));
+                    }
+                    let mut body = vec![0u8; body_len(17)?];
+                    cursor.read_exact(&mut body)?;
+                    snapshot = Some(if flags & FLAG_COMPRESSED != 0 { decompress(&body)? } else { body });
+                }
+                BLOCK_INPUT => {
+                    let count = cursor.read_u32::<LE>()?;
+                    let _reserved = cursor.read_u8()?;
+                    let _tstates = cursor.read_u32::<LE>()?;
+                    let flags = cursor.read_u32::<LE>()?;
+                    let mut body = vec![0u8; body_len(18)?];
+                    cursor.read_exact(&mut body)?;
+                    let body = if flags & FLAG_COMPRESSED != 0 { decompress(&body)? } else { body };
+
+                    let mut frame_cursor = Cursor::new(&body[..]);
+                    let mut input = InputState::default();
+                    for _ in 0..count {
+                        let instruction_count = frame_cursor.read_u16::<LE>()?;
+                        let in_count = frame_cursor.read_u16::<LE>()?;
+                        if in_count != REPEAT_LAST_FRAME {
+                            let mut values = vec![0u8; in_count as usize];
+                            frame_cursor.read_exact(&mut values)?;
+                            input = InputState::from_bytes(&values).ok_or_else(|| anyhow!("Corrupt recording frame"))?;
+                        }
+                        frames.push(RecordedFrame { instruction_count, input });
+                    }
+                }
+                _ => {
+                    // Skip unknown block
+                }
+            }
+
+            cursor.set_position(block_start + size);
+        }
+
+        let snapshot = snapshot.ok_or_else(|| anyhow!("Recording has no snapshot"))?;
+        Ok(Self { snapshot, frames })
+    }
+}
+
+/// Steps through a recording one frame at a time
+pub struct InputPlayer {
+    recording: InputRecording,
+    position: usize,
+    pub looping: bool,
+}
+
+impl InputPlayer {
+    pub fn new(recording: InputRecording, looping: bool) -> Self {
+        Self { recording, position: 0, looping }
+    }
+
+    pub fn snapshot(&self) -> &[u8] {
+        &self.recording.snapshot
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        let frame = self.recording.frames.get(self.position).copied();
+        if frame.is_some() {
+            self.position += 1;
+        }
+        frame
+    }
+
+    pub fn restart(&mut self) {
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustzx_core::zx::keys::ZXKey;
+
+    fn recording() -> InputRecording {
+        let mut recording = InputRecording::new(vec![0xAA; 49179]);
+        let mut input = InputState::default();
+        recording.record_frame(1000, input);
+        input.set_key(ZXKey::A, true);
+        recording.record_frame(70000, input);
+        recording.record_frame(1200, input);
+        recording
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = recording();
+        let parsed = InputRecording::parse(&original.encode().unwrap()).unwrap();
+        assert_eq!(parsed.snapshot, original.snapshot);
+        let frames: Vec<_> = parsed.frames.iter().map(|f| (f.instruction_count, f.input)).collect();
+        let expected: Vec<_> = original.frames.iter().map(|f| (f.instruction_count, f.input)).collect();
+        assert_eq!(frames, expected);
+        // The counter saturates
+        assert_eq!(parsed.frames[1].instruction_count, u16::MAX);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = recording().encode().unwrap();
+        data[..4].copy_from_slice(b"RZX!");
+        assert!(InputRecording::parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_blocks() {
+        let data = recording().encode().unwrap();
+        assert!(InputRecording::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_blocks() {
+        let mut data = recording().encode().unwrap();
+        // Size of the creator block, the first one after the 10-byte header
+        data[11..15].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(InputRecording::parse(&data).is_err());
+        data[11..15].copy_from_slice(&2u32.to_le_bytes());
+        assert!(InputRecording::parse(&data).is_err());
+    }
+}
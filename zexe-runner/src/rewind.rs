@@ -0,0 +1,117 @@
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+// One step back in time: the XOR between a state and the state captured
+// right after it, zlib compressed. Unchanged RAM XORs to zero and packs tightly.
+struct Delta {
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Delta {
+    fn encode(older: &[u8], newer: &[u8]) -> Option<Self> {
+        let size = older.len().max(newer.len());
+        let mut diff = vec![0u8; size];
+        for (i, d) in diff.iter_mut().enumerate() {
+            *d = older.get(i).copied().unwrap_or(0) ^ newer.get(i).copied().unwrap_or(0);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&diff).ok()?;
+        Some(Self { len: older.len(), data: encoder.finish().ok()? })
+    }
+
+    fn decode(&self, newer: &[u8]) -> Option<Vec<u8>> {
+        let mut diff = Vec::new();
+        ZlibDecoder::new(&self.data[..]).read_to_end(&mut diff).ok()?;
+        let mut older = newer.to_vec();
+        older.resize(diff.len(), 0);
+        for (o, d) in older.iter_mut().zip(&diff) {
+            *o ^= d;
+        }
+        older.truncate(self.len);
+        Some(older)
+    }
+}
+
+/// Ring buffer of machine states taken every `interval` frames.
+/// Only the newest state is kept whole; older ones are stored as deltas.
+pub struct RewindBuffer {
+    interval: u32,
+    max_entries: usize,
+    max_bytes: usize,
+    frame_counter: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+    bytes_used: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(seconds: u32, interval: u32, memory_mb: u32) -> Self {
+        let interval = interval.max(1);
+        Self {
+            interval,
+            max_entries: (seconds.saturating_mul(50) / interval) as usize,
+            max_bytes: memory_mb as usize * 1024 * 1024,
+            frame_counter: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            bytes_used: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_entries > 0 && self.max_bytes > 0
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// Called once per emulated frame; true when a state should be captured
+    pub fn tick(&mut self) -> bool {
+        self.frame_counter += 1;
+        if self.frame_counter >= self.interval {
+            self.frame_counter = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take()
+            && let Some(delta) = Delta::encode(&previous, &state) {
+                self.bytes_used += delta.data.len();
+                self.deltas.push_back(delta);
+                while self.deltas.len() > self.max_entries || self.bytes_used > self.max_bytes {
+                    match self.deltas.pop_front() {
+                        Some(old) => self.bytes_used -= old.data.len(),
+                        None => break,
+                    }
+                }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Steps one entry back and returns that state, or None at the start of history
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.as_ref()?;
+        let delta = self.deltas.back()?;
+        let older = delta.decode(latest)?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.bytes_used -= delta.data.len();
+        }
+        self.latest = Some(older.clone());
+        Some(older)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.bytes_used = 0;
+        self.frame_counter = 0;
+    }
+}
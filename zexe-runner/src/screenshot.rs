@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// Cuts a rectangle out of a frame
+pub fn crop(frame: &[u32], frame_w: usize, x: usize, y: usize, w: usize, h: usize) -> Vec<u32> {
+    frame
+        .chunks_exact(frame_w)
+        .skip(y)
+        .take(h)
+        .flat_map(|row| row[x..x + w].iter().copied())
+        .collect()
+}
+
+/// Nearest-neighbour upscale by an integer factor
+pub fn scale(pixels: &[u32], w: usize, factor: usize) -> Vec<u32> {
+    if factor <= 1 {
+        return pixels.to_vec();
+    }
+    let mut out = Vec::with_capacity(pixels.len() * factor * factor);
+    for row in pixels.chunks_exact(w) {
+        let scaled_row: Vec<u32> = row.iter().flat_map(|&p| std::iter::repeat_n(p, factor)).collect();
+        for _ in 0..factor {
+            out.extend_from_slice(&scaled_row);
+        }
+    }
+    out
+}
+
+/// Converts a bottom-up RGBA read-back from GL into top-down ARGB pixels
+pub fn from_gl_rgba(rgba: &[u8], w: usize, h: usize) -> Vec<u32> {
+    let mut out = Vec::with_capacity(w * h);
+    for row in rgba.chunks_exact(w * 4).take(h).rev() {
+        for p in row.chunks_exact(4) {
+            out.push(0xFF000000 | (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32);
+        }
+    }
+    out
+}
+
+/// Saves a screenshot as "<exe>-<timestamp>.png" in the given directory (or the user data directory)
+pub fn save(dir: Option<&str>, pixels: &[u32], w: usize, h: usize) -> Result<PathBuf> {
+    let path = match dir {
+        Some(dir) => crate::paths::timestamped_file_in(PathBuf::from(dir), "png"),
+        None => crate::paths::timestamped_file("png"),
+    }
+    .context("No screenshot directory")?;
+    crate::png::save_png(&path, pixels, w, h)?;
+    Ok(path)
+}
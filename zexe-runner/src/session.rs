@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use crate::paths;
+
+// Saved sessions are plain SNA images, zlib compressed, one per executable
+fn session_path() -> Option<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push(format!("{}.session", paths::exe_stem()));
+    Some(path)
+}
+
+pub fn save_session(state: &[u8]) -> Result<()> {
+    let path = session_path().context("No user data directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("Failed to create data directory")?;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(state)?;
+    std::fs::write(&path, encoder.finish()?).context("Failed to write session file")?;
+    Ok(())
+}
+
+pub fn load_session() -> Option<Vec<u8>> {
+    let compressed = std::fs::read(session_path()?).ok()?;
+    let mut decoder = ZlibDecoder::new(&compressed[..]);
+    let mut state = Vec::new();
+    decoder.read_to_end(&mut state).ok()?;
+    if state.is_empty() { None } else { Some(state) }
+}
+
+pub fn discard_session() {
+    if let Some(path) = session_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+use crate::paths;
+
+// The player's settings: config keys changed at runtime (volume, filter, border, ...),
+// one JSON file per executable in the user config directory. They are layered over
+// the embedded config and the files next to the executable (see config.rs).
+
+fn settings_path() -> Option<PathBuf> {
+    let mut path = paths::config_dir()?;
+    path.push(format!("{}.json", paths::exe_stem()));
+    Some(path)
+}
+
+/// The saved settings and the file they came from
+pub fn load() -> Option<(PathBuf, Map<String, Value>)> {
+    let path = settings_path()?;
+    let content = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(Value::Object(settings)) => Some((path, settings)),
+        _ => {
+            eprintln!("Ignoring invalid settings file {}", path.display());
+            None
+        }
+    }
+}
+
+pub fn save(key: &str, value: Value) -> Result<()> {
+    let path = settings_path().context("No user config directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("Failed to create config directory")?;
+    }
+    let mut settings = load().map(|(_, settings)| settings).unwrap_or_default();
+    settings.insert(key.to_string(), value);
+    settings.insert("config_version".to_string(), Value::from(crate::config::CONFIG_VERSION));
+    std::fs::write(&path, serde_json::to_string_pretty(&settings)?).context("Failed to write settings file")?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use anyhow::{Result, anyhow};
+
+// Saved machine states are SNA images (see App::capture_state). This gives
+// access to the registers and RAM banks they contain.
+
+const HEADER_SIZE: usize = 27;
+const BANK_SIZE: usize = 16384;
+const SNA_48K_SIZE: usize = HEADER_SIZE + 3 * BANK_SIZE;
+
+#[derive(Clone)]
+pub struct SnaImage {
+    pub header: [u8; HEADER_SIZE],
+    pub pc: u16,
+    // None on 48K machines
+    pub port_7ffd: Option<u8>,
+    // Always 8 banks; a 48K machine uses 5, 2 and 0 (0x4000, 0x8000, 0xC000)
+    pub banks: Vec<Vec<u8>>,
+}
+
+impl SnaImage {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < SNA_48K_SIZE {
+            return Err(anyhow!("SNA image too short"));
+        }
+        let mut header = [0u8; HEADER_SIZE];
+        header.copy_from_slice(&data[..HEADER_SIZE]);
+        let mut banks = vec![vec![0u8; BANK_SIZE]; 8];
+        let ram = &data[HEADER_SIZE..SNA_48K_SIZE];
+
+        if data.len() == SNA_48K_SIZE {
+            banks[5].copy_from_slice(&ram[..BANK_SIZE]);
+            banks[2].copy_from_slice(&ram[BANK_SIZE..2 * BANK_SIZE]);
+            banks[0].copy_from_slice(&ram[2 * BANK_SIZE..]);
+
+            let mut image = Self { header, pc: 0, port_7ffd: None, banks };
+            // 48K SNA keeps PC on the stack
+            let sp = image.reg16(23);
+            image.pc = u16::from_le_bytes([image.read(sp), image.read(sp.wrapping_add(1))]);
+            image.set_reg16(23, sp.wrapping_add(2));
+            Ok(image)
+        } else {
+            if data.len() < SNA_48K_SIZE + 4 + 5 * BANK_SIZE {
+                return Err(anyhow!("128K SNA image too short"));
+            }
+            let ext = &data[SNA_48K_SIZE..SNA_48K_SIZE + 4];
+            let pc = u16::from_le_bytes([ext[0], ext[1]]);
+            let port_7ffd = ext[2];
+            let paged = (port_7ffd & 0x07) as usize;
+
+            banks[5].copy_from_slice(&ram[..BANK_SIZE]);
+            banks[2].copy_from_slice(&ram[BANK_SIZE..2 * BANK_SIZE]);
+            banks[paged].copy_from_slice(&ram[2 * BANK_SIZE..]);
+
+            let mut rest = data[SNA_48K_SIZE + 4..].chunks_exact(BANK_SIZE);
+            for (i, bank) in banks.iter_mut().enumerate() {
+                if i == 5 || i == 2 || i == paged {
+                    continue;
+                }
+                let chunk = rest.next().ok_or_else(|| anyhow!("Missing RAM bank {}", i))?;
+                bank.copy_from_slice(chunk);
+            }
+            Ok(Self { header, pc, port_7ffd: Some(port_7ffd), banks })
+        }
+    }
+
+    /// A 128K SNA has 5 more banks, or 6 when bank 2 or 5 is also paged in at 0xC000
+    pub fn is_128k_size(len: usize) -> bool {
+        len == SNA_48K_SIZE + 4 + 5 * BANK_SIZE || len == SNA_48K_SIZE + 4 + 6 * BANK_SIZE
+    }
+
+    pub fn is_128k(&self) -> bool {
+        self.port_7ffd.is_some()
+    }
+
+    pub fn reg16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.header[offset], self.header[offset + 1]])
+    }
+
+    pub fn set_reg16(&mut self, offset: usize, value: u16) {
+        self.header[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Bank mapped at a RAM address (0x4000-0xFFFF), following the current paging
+    pub fn bank_at(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x0000..=0x3FFF => None,
+            0x4000..=0x7FFF => Some(5),
+            0x8000..=0xBFFF => Some(2),
+            _ => Some(self.port_7ffd.map(|p| (p & 0x07) as usize).unwrap_or(0)),
+        }
+    }
+
+    /// Address at which an offset into a RAM bank can be reached, if the bank is paged in
+    pub fn paged_address(&self, bank: u8, addr: u16) -> Option<u16> {
+        [0x4000u16, 0x8000, 0xC000].into_iter()
+            .find(|&base| self.bank_at(base) == Some(bank as usize))
+            .map(|base| base | (addr & 0x3FFF))
+    }
+
+    /// Reads the address space as the CPU sees it. ROM is not part of the image and reads as 0xFF.
+    pub fn read(&self, addr: u16) -> u8 {
+        match self.bank_at(addr) {
+            Some(bank) => self.banks[bank][addr as usize & 0x3FFF],
+            None => 0xFF,
+        }
+    }
+
+    /// Same as `read`, with 0x0000-0x3FFF taken from the ROM as loaded (32K on the 128K,
+    /// where bit 4 of port 0x7FFD selects the page)
+    pub fn read_with_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr >= 0x4000 {
+            return self.read(addr);
+        }
+        let page = match self.port_7ffd {
+            Some(port) if rom.len() > 0x4000 => ((port >> 4) & 1) as usize,
+            _ => 0,
+        };
+        rom.get(page * 0x4000 + addr as usize).copied().unwrap_or(0xFF)
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if let Some(bank) = self.bank_at(addr) {
+            self.banks[bank][addr as usize & 0x3FFF] = value;
+        }
+    }
+
+    /// Encodes the image back into an SNA file (for loading it into the emulator)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut image = self.clone();
+
+        match self.port_7ffd {
+            None => {
+                // Push PC back onto the stack
+                let sp = image.reg16(23).wrapping_sub(2);
+                image.set_reg16(23, sp);
+                image.write(sp, (self.pc & 0xFF) as u8);
+                image.write(sp.wrapping_add(1), (self.pc >> 8) as u8);
+
+                let mut sna = Vec::with_capacity(SNA_48K_SIZE);
+                sna.extend_from_slice(&image.header);
+                sna.extend_from_slice(&image.banks[5]);
+                sna.extend_from_slice(&image.banks[2]);
+                sna.extend_from_slice(&image.banks[0]);
+                sna
+            }
+            Some(port_7ffd) => {
+                let paged = (port_7ffd & 0x07) as usize;
+                let mut sna = Vec::with_capacity(131103);
+                sna.extend_from_slice(&image.header);
+                sna.extend_from_slice(&image.banks[5]);
+                sna.extend_from_slice(&image.banks[2]);
+                sna.extend_from_slice(&image.banks[paged]);
+                sna.push((self.pc & 0xFF) as u8);
+                sna.push((self.pc >> 8) as u8);
+                sna.push(port_7ffd);
+                sna.push(0); // TR-DOS
+                for (i, bank) in image.banks.iter().enumerate() {
+                    if i != 5 && i != 2 && i != paged {
+                        sna.extend_from_slice(bank);
+                    }
+                }
+                sna
+            }
+        }
+    }
+
+    /// RAM contents: the 48K address space, or all eight 128K banks in order
+    pub fn ram_dump(&self) -> Vec<u8> {
+        let order: &[usize] = if self.is_128k() { &[0, 1, 2, 3, 4, 5, 6, 7] } else { &[5, 2, 0] };
+        order.iter().flat_map(|i| self.banks[*i].iter().copied()).collect()
+    }
+}
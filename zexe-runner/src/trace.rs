@@ -0,0 +1,160 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::cli;
+use crate::disasm;
+use crate::sna::SnaImage;
+
+// Per-instruction execution trace (--trace FILE), gzip-compressed, one line per instruction:
+//
+//   frame  T-states  PC  opcode bytes  disassembly  registers  7FFD
+//
+// The debug hook stops before every instruction while tracing, and the registers are
+// read from a saved state, so emulation is much slower than usual. ROM is not part of
+// the saved state, so the tracer is given the ROM that was loaded.
+
+const DEFAULT_MAX_MB: u64 = 64;
+// How often the compressed size is compared with the limit
+const SIZE_CHECK_LINES: u64 = 4096;
+
+#[derive(Clone)]
+pub struct TraceOptions {
+    path: PathBuf,
+    // Inclusive frame range, counted from the start of emulation
+    frames: Option<(u32, u32)>,
+    // Tracing starts when PC reaches `from` and ends after `to`
+    from: Option<u16>,
+    to: Option<u16>,
+    max_bytes: u64,
+}
+
+impl TraceOptions {
+    pub fn from_args(args: &[String]) -> Result<Option<Self>> {
+        let Some(path) = cli::required(args, "--trace")? else { return Ok(None) };
+        let frames = match cli::required(args, "--trace-frames")? {
+            // "START-END" or a single frame
+            Some(range) => {
+                let (start, end) = range.split_once('-').unwrap_or((range, range));
+                let start = start.trim().parse::<u32>().context("Invalid --trace-frames start")?;
+                let end = end.trim().parse::<u32>().context("Invalid --trace-frames end")?;
+                if end < start {
+                    return Err(anyhow!("--trace-frames range is reversed"));
+                }
+                Some((start, end))
+            }
+            None => None,
+        };
+        let max_mb = match cli::required(args, "--trace-max-mb")? {
+            Some(mb) => mb.parse::<u64>().context("Invalid --trace-max-mb")?,
+            None => DEFAULT_MAX_MB,
+        };
+        Ok(Some(Self {
+            path: PathBuf::from(path),
+            frames,
+            from: cli::required(args, "--trace-from")?.map(cli::parse_u16).transpose()?,
+            to: cli::required(args, "--trace-to")?.map(cli::parse_u16).transpose()?,
+            max_bytes: max_mb.max(1) * 1024 * 1024,
+        }))
+    }
+}
+
+pub struct Tracer {
+    options: TraceOptions,
+    writer: GzEncoder<File>,
+    frame: u32,
+    next_frame: u32,
+    // ROM pages as loaded: one for 48K, two (editor, then 48K BASIC) for 128K
+    rom: Vec<u8>,
+    // Estimated T-states since the frame start
+    t_states: u32,
+    // PC, length and timing of the last instruction, to add its T-states
+    previous: Option<(u16, u16, (u32, u32))>,
+    active: bool,
+    lines: u64,
+    done: bool,
+}
+
+impl Tracer {
+    pub fn start(options: TraceOptions, rom: Vec<u8>) -> Result<Self> {
+        let file = File::create(&options.path).with_context(|| format!("Failed to create {}", options.path.display()))?;
+        let mut writer = GzEncoder::new(file, Compression::fast());
+        writeln!(writer, "# frame tstates pc bytes instruction registers 7ffd (T-states are uncontended estimates)")?;
+        let active = options.from.is_none();
+        Ok(Self { options, writer, rom, frame: 0, next_frame: 0, t_states: 0, previous: None, active, lines: 0, done: false })
+    }
+
+    /// Called at the start of each emulated frame
+    pub fn begin_frame(&mut self) {
+        self.frame = self.next_frame;
+        self.next_frame += 1;
+        self.t_states = 0;
+        self.previous = None;
+        if self.options.frames.is_some_and(|(_, end)| self.frame > end) {
+            self.done = true;
+        }
+    }
+
+    /// Whether the hook should stop before each instruction of the current frame
+    pub fn wants_frame(&self) -> bool {
+        !self.done && self.options.frames.is_none_or(|(start, end)| (start..=end).contains(&self.frame))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Logs the instruction about to run, from the machine state before it
+    pub fn log(&mut self, image: &SnaImage) -> Result<()> {
+        if self.done {
+            return Ok(());
+        }
+        let pc = image.pc;
+        // The previous instruction was taken if it did not fall through to this one
+        if let Some((previous_pc, length, (not_taken, taken))) = self.previous.take() {
+            let t = if pc == previous_pc.wrapping_add(length) { not_taken } else { taken };
+            self.t_states += t;
+        }
+
+        let read = |a: u16| image.read_with_rom(&self.rom, a);
+        let instruction = disasm::disassemble(&read, pc);
+        self.previous = Some((pc, instruction.length, disasm::t_states(&read, pc)));
+        let bytes: String = (0..instruction.length).map(|n| format!("{:02X}", read(pc.wrapping_add(n)))).collect();
+        let text = instruction.text;
+
+        if !self.active {
+            if self.options.from != Some(pc) {
+                return Ok(());
+            }
+            self.active = true;
+        }
+
+        let h = &image.header;
+        let paging = image.port_7ffd.map(|p| format!("{:02X}", p)).unwrap_or_else(|| "--".to_string());
+        writeln!(
+            self.writer,
+            "{:6} {:5} {:04X} {:<8} {:<18} AF={:04X} BC={:04X} DE={:04X} HL={:04X} IX={:04X} IY={:04X} SP={:04X} AF'={:04X} BC'={:04X} DE'={:04X} HL'={:04X} IR={:02X}{:02X} IM={} 7FFD={}",
+            self.frame, self.t_states, pc, bytes, text,
+            image.reg16(21), image.reg16(13), image.reg16(11), image.reg16(9), image.reg16(17), image.reg16(15), image.reg16(23),
+            image.reg16(7), image.reg16(5), image.reg16(3), image.reg16(1), h[0], h[20], h[25], paging,
+        )?;
+        self.lines += 1;
+
+        if self.options.to == Some(pc) {
+            self.done = true;
+        }
+        if self.lines.is_multiple_of(SIZE_CHECK_LINES) && self.writer.get_ref().metadata()?.len() >= self.options.max_bytes {
+            writeln!(self.writer, "# size limit reached")?;
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<PathBuf> {
+        self.writer.finish()?;
+        Ok(self.options.path)
+    }
+}
@@ -86,6 +86,7 @@ pub fn convert_z80_to_sna(z80_data: &[u8]) -> Result<(Vec<u8>, ZXMachine)> {
         // Valid header lens: 23 (v2), 54/55 (v3)
         if header_len == 23 { version = 2; } 
         else if header_len == 54 || header_len == 55 { version = 3; }
+        else { return Err(anyhow!("Unknown Z80 header length {}", header_len)); }
         
         for _ in 0..skip {
             cursor.read_u8()?;
@@ -101,6 +102,14 @@ pub fn convert_z80_to_sna(z80_data: &[u8]) -> Result<(Vec<u8>, ZXMachine)> {
         let data_rem = &z80_data[pos_start..];
         let compressed = (byte12 & 0x20) != 0;
         
+        // Anything else is not a Z80 file (create_emulator tries this before 128K SNA)
+        if compressed && !data_rem.ends_with(&[0x00, 0xED, 0xED, 0x00]) {
+            return Err(anyhow!("Z80 v1 data has no end marker"));
+        }
+        if !compressed && data_rem.len() != 49152 {
+            return Err(anyhow!("Z80 v1 data is {} bytes, expected 49152", data_rem.len()));
+        }
+
         let mut ram_48k = vec![0u8; 49152];
         if compressed {
             decompress_z80_block(data_rem, &mut ram_48k)?;